@@ -21,7 +21,7 @@ impl ScriptNode {
             env,
             subnodes: Slab::new(),
         };
-        _ = v.as_object().unwrap().clone()
+        v.as_object().unwrap().clone()
             .into_iter()
             .for_each(|(key, val)| {
                 // println!("--- > {}: {}",&key,val.as_str().unwrap_or("default"));
@@ -30,11 +30,11 @@ impl ScriptNode {
                     node.component = val.as_str().unwrap_or("default").to_string();
                 } else if key.eq_ignore_ascii_case("env") {
                     if let Value::Object(map) = val {
-                        node.env.extend(map.clone().into_iter());
+                        node.env.extend(map.clone());
                     }
                 } else if key.eq_ignore_ascii_case("args") {
                     if let Value::Object(map) = val {
-                        node.component_args.extend(map.clone().into_iter());
+                        node.component_args.extend(map.clone());
                     }
                 } else if key.eq_ignore_ascii_case("label") {
                     node.label = Some(val.as_str().unwrap().to_string());
@@ -48,7 +48,7 @@ impl ScriptNode {
                 }
             });
 
-        return  node;
+        node
     }
 
 }
@@ -3,10 +3,11 @@ use std::{time::Duration, fs::File, sync::{Arc, Mutex}};
 use async_trait::async_trait;
 use rumeter_component::{
     Controller,
+    context::VUContext,
     record::RecordData, 
     samplers::{http:: {HeaderValue, HeaderMap}, gql::GraphQLSampler}, 
-    Sampler, 
     group::ThreadGroup, 
+    config::{csv::CsvDataSetConfig, cookie::CookieManager},
     output::file_output::FileOutput,
 };
 use serde::Serialize;
@@ -20,11 +21,15 @@ async fn main() ->  Result<(), Box<dyn std::error::Error>> {
         .with_span_events(FmtSpan::CLOSE)
         .init();
 
-    let group = ThreadGroup::new(10, Duration::from_secs(1), -1, Some(Duration::from_secs(300)));
+    // one login per row, the header row names the variables
+    let users = CsvDataSetConfig::new(concat!(env!("CARGO_MANIFEST_DIR"), "/users.csv")).build()?;
+    let group = ThreadGroup::new(10, Duration::from_secs(1), -1, Some(Duration::from_secs(300)))
+        .csv_data_set(users)
+        .cookie_manager(CookieManager::new().clear_each_iteration(true));
     // let group = ThreadGroup::new(10, Duration::from_secs(1), 10, None);
 
     let out = FileOutput::new(File::create("gql.rtl").unwrap());
-    group.start(SimpleController::default(), Arc::new(Mutex::new(out))).await;
+    group.start(SimpleController, Arc::new(Mutex::new(out))).await;
     info!("test finished");
     Ok(())
 }
@@ -33,7 +38,7 @@ async fn main() ->  Result<(), Box<dyn std::error::Error>> {
 struct SimpleController;
 #[async_trait]
 impl Controller for SimpleController {
-    async fn run(&self) -> Vec<RecordData> {
+    async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
         let query = r#"
         query Login($email: String, $password: String){
             login(email: $email, password: $password){
@@ -44,8 +49,8 @@ impl Controller for SimpleController {
         }
         "#;
         let vars = LoginVars{
-            email: "liudao@testops.vip".to_string(),
-            password: "123456".to_string(),
+            email: "${email}".to_string(),
+            password: "${password}".to_string(),
         };
         let mut headers = HeaderMap::new();
         headers.append("Access-Token", HeaderValue::from_static("123456"));
@@ -56,7 +61,7 @@ impl Controller for SimpleController {
             headers, 
             Some(vars),
         );
-        let re = samp.run().await;
+        let re = ctx.sample(&samp).await;
         vec![re]
     }
 }
@@ -3,10 +3,11 @@ use async_trait::async_trait;
 use rumeter_component::{
     group::ThreadGroup, 
     samplers::http::{HttpSampler, HeaderMap, HeaderValue, Method},
+    assertions::response::ResponseCodeAssertion,
     output::file_output::FileOutput, 
     Controller, 
+    context::VUContext,
     record::RecordData, 
-    Sampler, 
 };
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing::*;
@@ -26,7 +27,7 @@ async fn main() ->  Result<(), Box<dyn std::error::Error>> {
     // define the output file. this rtl file will record the load test data
     let out = FileOutput::new(File::create("http.rtl").unwrap());
     // start the load test
-    group.start(SimpleController::default(), Arc::new(Mutex::new(out))).await;
+    group.start(SimpleController, Arc::new(Mutex::new(out))).await;
     info!("test finished");
     Ok(())
 }
@@ -39,7 +40,7 @@ pub struct SimpleController;
 #[async_trait]
 impl Controller for SimpleController {
     // you can define all your load testing logic in this function named "run"
-    async fn run(&self) -> Vec<RecordData> {
+    async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
         let mut headers = HeaderMap::new();
         headers.append("Access-Token", HeaderValue::from_static("123456"));
         let samp = HttpSampler::new(
@@ -48,8 +49,8 @@ impl Controller for SimpleController {
             Method::GET, 
             headers,
             None,
-        );
-        let re = samp.run().await;
+        ).assertion(ResponseCodeAssertion::new(&[200]));
+        let re = ctx.sample(&samp).await;
         vec![re]
     }
 }
\ No newline at end of file
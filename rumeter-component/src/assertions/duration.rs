@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use crate::{Assertion, record::RecordData};
+
+/// Fails samples whose elapsed time is over a limit.
+#[derive(Clone, Debug)]
+pub struct DurationAssertion {
+    label: String,
+    max: Duration,
+}
+
+impl DurationAssertion {
+    pub fn new(max: Duration) -> Self {
+        Self { label: "Duration Assertion".to_string(), max }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+}
+
+impl Assertion for DurationAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let max = self.max.as_millis() as u64;
+        if data.get_elapsed() <= max {
+            Ok(())
+        } else {
+            Err(format!("elapsed {} ms is over {} ms", data.get_elapsed(), max))
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+}
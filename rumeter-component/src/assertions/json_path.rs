@@ -0,0 +1,66 @@
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+use crate::{Assertion, record::RecordData};
+
+/// Evaluates a JSONPath expression on the response body. Passes when the path
+/// matches something and, if an expected value is set, the first match equals it.
+#[derive(Clone, Debug)]
+pub struct JsonPathAssertion {
+    label: String,
+    path: JsonPath,
+    expression: String,
+    expected: Option<Value>,
+    negate: bool,
+}
+
+impl JsonPathAssertion {
+    pub fn new(path: &str, expected: Option<Value>) -> Result<Self, serde_json_path::ParseError> {
+        Ok(Self {
+            label: "JSON Assertion".to_string(),
+            path: JsonPath::parse(path)?,
+            expression: path.to_string(),
+            expected,
+            negate: false,
+        })
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    /// Pass when the path does NOT match, or its value differs from the expected one.
+    pub fn negate(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    fn check(&self, body: &str) -> Result<(), String> {
+        let json: Value = serde_json::from_str(body).map_err(|e| format!("response is not json, {}", e))?;
+        let nodes = self.path.query(&json);
+        let first = match nodes.first() {
+            Some(v) => v,
+            None => return Err(format!("no value at {}", self.expression)),
+        };
+        match &self.expected {
+            Some(expected) if expected != first => Err(format!("value at {} is {}, expected {}", self.expression, first, expected)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Assertion for JsonPathAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let body = data.response_result().map(|r| r.response_data()).unwrap_or("");
+        match (self.check(body), self.negate) {
+            (Ok(()), false) | (Err(_), true) => Ok(()),
+            (Err(reason), false) => Err(reason),
+            (Ok(()), true) => Err(format!("{} should not match", self.expression)),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::{Assertion, record::RecordData};
+
+pub mod response;
+pub mod json_path;
+pub mod size;
+pub mod duration;
+
+pub type AssertionRef = Arc<dyn Assertion>;
+
+/// Run every assertion against `data`. Failures mark the sample as failed and
+/// are appended to its failure message as `name: reason`.
+pub fn apply(assertions: &[AssertionRef], data: &mut RecordData) {
+    let mut failures: Vec<String> = assertions.iter()
+        .filter_map(|a| a.assert(data).err().map(|reason| format!("{}: {}", a.name(), reason)))
+        .collect();
+    if failures.is_empty() {
+        return;
+    }
+    if let Some(msg) = data.get_failure_message() {
+        failures.insert(0, msg.to_string());
+    }
+    data.success(false);
+    data.failure_message(Some(failures.join("; ")));
+}
+
+/// How a text is matched, same rules as the JMeter Response Assertion.
+#[derive(Clone, Debug)]
+pub enum TextPattern {
+    /// the text contains the string.
+    Substring(String),
+    /// the text equals the string.
+    Equals(String),
+    /// the regex matches some part of the text.
+    Contains(Regex),
+    /// the regex matches the whole text.
+    Matches(Regex),
+}
+
+impl TextPattern {
+    pub fn contains(regex: &str) -> Result<Self, regex::Error> {
+        Ok(TextPattern::Contains(Regex::new(regex)?))
+    }
+
+    pub fn matches(regex: &str) -> Result<Self, regex::Error> {
+        Ok(TextPattern::Matches(Regex::new(&format!("^(?:{})$", regex))?))
+    }
+
+    pub fn test(&self, text: &str) -> bool {
+        match self {
+            TextPattern::Substring(s) => text.contains(s.as_str()),
+            TextPattern::Equals(s) => text == s,
+            TextPattern::Contains(r) | TextPattern::Matches(r) => r.is_match(text),
+        }
+    }
+}
+
+impl std::fmt::Display for TextPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextPattern::Substring(s) => write!(f, "contain \"{}\"", s),
+            TextPattern::Equals(s) => write!(f, "equal \"{}\"", s),
+            TextPattern::Contains(r) => write!(f, "contain /{}/", r),
+            TextPattern::Matches(r) => write!(f, "match /{}/", r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod assertions_tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use serde_json::json;
+
+    use crate::record::{RecordData, ResponseResult};
+
+    use super::{apply, TextPattern, AssertionRef, response::*, json_path::JsonPathAssertion, size::*, duration::DurationAssertion};
+
+    fn record(code: u16, body: &str) -> RecordData {
+        let mut headers = HashMap::new();
+        headers.insert("content-type".to_string(), "application/json".to_string());
+        RecordData::new(
+            0, 120, "login".to_string(), code, "OK".to_string(), "".to_string(), "text".to_string(),
+            true, None, 100 + body.len() as u64, 50, 0, 0, "http://localhost/login".to_string(), 80, 0, 0,
+            Some(ResponseResult::new(headers, body.to_string())),
+        )
+    }
+
+    #[test]
+    fn passing_assertions() {
+        let assertions: Vec<AssertionRef> = vec![
+            Arc::new(ResponseCodeAssertion::new(&[200, 201])),
+            Arc::new(ResponseTextAssertion::new(TextPattern::Substring("token".to_string()))),
+            Arc::new(ResponseTextAssertion::new(TextPattern::contains("\"error\"").unwrap()).negate()),
+            Arc::new(HeaderAssertion::new("Content-Type", Some(TextPattern::matches("application/.*").unwrap()))),
+            Arc::new(JsonPathAssertion::new("$.data.token", Some(json!("abc"))).unwrap()),
+            Arc::new(SizeAssertion::new(Comparison::Less, 1024)),
+            Arc::new(DurationAssertion::new(Duration::from_millis(200))),
+        ];
+        let mut re = record(200, r#"{"data": {"token": "abc"}}"#);
+        apply(&assertions, &mut re);
+        assert!(re.is_success());
+        assert_eq!(re.get_failure_message(), None);
+    }
+
+    #[test]
+    fn failing_assertions() {
+        let assertions: Vec<AssertionRef> = vec![
+            Arc::new(ResponseCodeAssertion::new(&[200])),
+            Arc::new(JsonPathAssertion::new("$.data.token", None).unwrap().label("token")),
+            Arc::new(DurationAssertion::new(Duration::from_millis(100))),
+            Arc::new(CookieAssertion::new("sid", None)),
+        ];
+        let mut re = record(200, r#"{"errors": [{"message": "bad password"}]}"#);
+        apply(&assertions, &mut re);
+        assert!(!re.is_success());
+        assert_eq!(
+            re.get_failure_message(),
+            Some("token: no value at $.data.token; Duration Assertion: elapsed 120 ms is over 100 ms; Cookie Assertion: cookie sid is missing"),
+        );
+    }
+}
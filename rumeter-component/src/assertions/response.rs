@@ -0,0 +1,166 @@
+use crate::{Assertion, record::RecordData};
+
+use super::TextPattern;
+
+/// Passes when the response code is one of the expected codes. An expected
+/// error code does not fail the sample of a http sampler.
+#[derive(Clone, Debug)]
+pub struct ResponseCodeAssertion {
+    label: String,
+    codes: Vec<u16>,
+}
+
+impl ResponseCodeAssertion {
+    pub fn new(codes: &[u16]) -> Self {
+        Self { label: "Response Code Assertion".to_string(), codes: codes.to_vec() }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+}
+
+impl Assertion for ResponseCodeAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let code = data.get_response_code();
+        if self.codes.contains(&code) {
+            Ok(())
+        } else {
+            Err(format!("response code {} is not in {:?}", code, self.codes))
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+
+    fn expected_codes(&self) -> &[u16] {
+        &self.codes
+    }
+}
+
+/// Tests the response body against a [`TextPattern`].
+#[derive(Clone, Debug)]
+pub struct ResponseTextAssertion {
+    label: String,
+    pattern: TextPattern,
+    negate: bool,
+}
+
+impl ResponseTextAssertion {
+    pub fn new(pattern: TextPattern) -> Self {
+        Self { label: "Response Assertion".to_string(), pattern, negate: false }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    /// Pass when the pattern does NOT match.
+    pub fn negate(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+}
+
+impl Assertion for ResponseTextAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let body = data.response_result().map(|r| r.response_data()).unwrap_or("");
+        if self.pattern.test(body) != self.negate {
+            Ok(())
+        } else if self.negate {
+            Err(format!("response body should not {}", self.pattern))
+        } else {
+            Err(format!("response body does not {}", self.pattern))
+        }
+    }
+}
+
+/// Passes when a response header exists and, if a pattern is given, its value matches.
+#[derive(Clone, Debug)]
+pub struct HeaderAssertion {
+    label: String,
+    header: String,
+    pattern: Option<TextPattern>,
+}
+
+impl HeaderAssertion {
+    pub fn new(header: &str, pattern: Option<TextPattern>) -> Self {
+        Self { label: "Header Assertion".to_string(), header: header.to_string(), pattern }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+}
+
+impl Assertion for HeaderAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let value = data.response_result().and_then(|r| r.header(&self.header));
+        match (value, &self.pattern) {
+            (None, _) => Err(format!("header {} is missing", self.header)),
+            (Some(_), None) => Ok(()),
+            (Some(v), Some(p)) if p.test(v) => Ok(()),
+            (Some(v), Some(p)) => Err(format!("header {} value \"{}\" does not {}", self.header, v, p)),
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+}
+
+/// Passes when the virtual user holds a cookie for the url of the sample and,
+/// if a pattern is given, its value matches.
+#[derive(Clone, Debug)]
+pub struct CookieAssertion {
+    label: String,
+    cookie: String,
+    pattern: Option<TextPattern>,
+}
+
+impl CookieAssertion {
+    pub fn new(cookie: &str, pattern: Option<TextPattern>) -> Self {
+        Self { label: "Cookie Assertion".to_string(), cookie: cookie.to_string(), pattern }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+}
+
+impl Assertion for CookieAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let value = data.response_result().and_then(|r| r.cookie(&self.cookie));
+        match (value, &self.pattern) {
+            (None, _) => Err(format!("cookie {} is missing", self.cookie)),
+            (Some(_), None) => Ok(()),
+            (Some(v), Some(p)) if p.test(v) => Ok(()),
+            (Some(v), Some(p)) => Err(format!("cookie {} value \"{}\" does not {}", self.cookie, v, p)),
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+}
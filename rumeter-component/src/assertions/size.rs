@@ -0,0 +1,94 @@
+use crate::{Assertion, record::RecordData};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn compare(&self, actual: u64, expected: u64) -> bool {
+        match self {
+            Comparison::Equal => actual == expected,
+            Comparison::NotEqual => actual != expected,
+            Comparison::Greater => actual > expected,
+            Comparison::Less => actual < expected,
+            Comparison::GreaterOrEqual => actual >= expected,
+            Comparison::LessOrEqual => actual <= expected,
+        }
+    }
+
+    fn symbol(&self) -> &str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Greater => ">",
+            Comparison::Less => "<",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::LessOrEqual => "<=",
+        }
+    }
+}
+
+/// Which part of the response is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeScope {
+    /// status line, headers and body as received.
+    Full,
+    /// body as received.
+    Body,
+    /// body after content decoding.
+    DecodedBody,
+}
+
+/// Compares the size of the response, in bytes, with an expected size.
+#[derive(Clone, Debug)]
+pub struct SizeAssertion {
+    label: String,
+    comparison: Comparison,
+    size: u64,
+    scope: SizeScope,
+}
+
+impl SizeAssertion {
+    pub fn new(comparison: Comparison, size: u64) -> Self {
+        Self { label: "Size Assertion".to_string(), comparison, size, scope: SizeScope::Full }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    pub fn scope(mut self, scope: SizeScope) -> Self {
+        self.scope = scope;
+        self
+    }
+}
+
+impl Assertion for SizeAssertion {
+    fn name(&self) -> String {
+        self.label.clone()
+    }
+
+    fn assert(&self, data: &RecordData) -> Result<(), String> {
+        let actual = match self.scope {
+            SizeScope::Full => data.get_bytes(),
+            SizeScope::Body => data.get_body_size(),
+            SizeScope::DecodedBody => data.get_decoded_body_size(),
+        };
+        if self.comparison.compare(actual, self.size) {
+            Ok(())
+        } else {
+            Err(format!("size {} bytes, expected {} {}", actual, self.comparison.symbol(), self.size))
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use cookie_store::{CookieStore, RawCookie};
+use reqwest::{Url, header::{HeaderMap, HeaderValue, SET_COOKIE}};
+use tracing::*;
+
+/// Options of the cookie jars, see [`crate::group::ThreadGroup::cookie_manager`].
+#[derive(Debug, Clone, Default)]
+pub struct CookieManager {
+    clear_each_iteration: bool,
+    cookies: Vec<UserCookie>,
+}
+
+#[derive(Debug, Clone)]
+struct UserCookie {
+    name: String,
+    value: String,
+    domain: String,
+    path: String,
+}
+
+impl CookieManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start every iteration with a new jar holding only the cookies given by [`CookieManager::cookie`].
+    pub fn clear_each_iteration(mut self, clear_each_iteration: bool) -> Self {
+        self.clear_each_iteration = clear_each_iteration;
+        self
+    }
+
+    /// A cookie every jar starts with, sent to `domain` and its sub domains under `path`.
+    pub fn cookie(mut self, name: &str, value: &str, domain: &str, path: &str) -> Self {
+        self.cookies.push(UserCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: domain.to_string(),
+            path: path.to_string(),
+        });
+        self
+    }
+
+    pub fn is_clear_each_iteration(&self) -> bool {
+        self.clear_each_iteration
+    }
+
+    /// A new jar with the cookies of the manager.
+    pub fn jar(&self) -> CookieJar {
+        let mut jar = CookieJar::new();
+        for c in &self.cookies {
+            jar.insert(&c.name, &c.value, &c.domain, &c.path);
+        }
+        jar
+    }
+}
+
+/// Cookies of a virtual user. Kept in its [`crate::context::VUContext`], read it
+/// with `ctx.get::<CookieJar>()`. Samples without a jar send no cookie.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    store: CookieStore,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a cookie for `domain` and its sub domains under `path`.
+    pub fn insert(&mut self, name: &str, value: &str, domain: &str, path: &str) {
+        let url = match Url::parse(&format!("http://{}{}", domain, path)) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("cannot add cookie {} for {}{}: {}", name, domain, path, e);
+                return;
+            },
+        };
+        let cookie = RawCookie::build((name.to_string(), value.to_string()))
+            .domain(domain.to_string())
+            .path(path.to_string())
+            .build();
+        if let Err(e) = self.store.insert_raw(&cookie, &url) {
+            warn!("cannot add cookie {} for {}{}: {}", name, domain, path, e);
+        }
+    }
+
+    /// Value of the first unexpired cookie called `name`, whatever its domain.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.store.iter_unexpired().find(|c| c.name() == name).map(|c| c.value())
+    }
+
+    /// Name and value of the unexpired cookies.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.store.iter_unexpired().map(|c| (c.name(), c.value()))
+    }
+
+    /// Cookies a request to `url` sends.
+    pub fn matches(&self, url: &Url) -> HashMap<String, String> {
+        self.store.get_request_values(url)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.store.clear();
+    }
+
+    /// Value of the `Cookie` header of a request to `url`.
+    pub(crate) fn request_header(&self, url: &Url) -> Option<HeaderValue> {
+        let value = self.store.get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if value.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&value).ok()
+    }
+
+    /// Keep the cookies set by a response to `url`.
+    pub(crate) fn store_response(&mut self, headers: &HeaderMap, url: &Url) {
+        let cookies = headers.get_all(SET_COOKIE).iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| RawCookie::parse(v.to_string()).ok());
+        self.store.store_response_cookies(cookies, url);
+    }
+}
+
+#[cfg(test)]
+mod cookie_tests {
+    use reqwest::{Url, header::{HeaderMap, HeaderValue, SET_COOKIE}};
+
+    use super::CookieManager;
+
+    #[test]
+    fn keep_response_cookies() {
+        let mut jar = CookieManager::new().cookie("lang", "en", "example.com", "/").jar();
+        let login = Url::parse("http://api.example.com/login").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static("sid=abc; Path=/; HttpOnly"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("admin=1; Path=/admin"));
+        jar.store_response(&headers, &login);
+
+        assert_eq!(jar.get("sid"), Some("abc"));
+        let home = Url::parse("http://api.example.com/home").unwrap();
+        let mut sent: Vec<String> = jar.request_header(&home).unwrap().to_str().unwrap()
+            .split("; ").map(str::to_string).collect();
+        sent.sort();
+        assert_eq!(sent, ["lang=en", "sid=abc"]);
+        assert_eq!(jar.matches(&Url::parse("http://other.org/").unwrap()).len(), 0);
+        assert_eq!(jar.matches(&Url::parse("http://api.example.com/admin/users").unwrap()).get("admin").map(String::as_str), Some("1"));
+
+        headers.clear();
+        headers.append(SET_COOKIE, HeaderValue::from_static("sid=; Max-Age=0; Path=/"));
+        jar.store_response(&headers, &login);
+        assert_eq!(jar.get("sid"), None);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{fs::File, io::Read, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+use crate::variables::Variables;
+
+/// Value given to every variable once a data set without recycling is exhausted.
+pub const EOF_VALUE: &str = "<EOF>";
+
+/// Who reads from the same position of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShareMode {
+    /// All the threads of the group share one cursor, each row goes to one thread.
+    #[default]
+    Group,
+    /// Every thread reads the whole file with its own cursor.
+    Thread,
+}
+
+/// What a thread does once the cursor is past the last row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnEof {
+    /// Start over from the first row.
+    #[default]
+    Recycle,
+    /// Stop the thread before its next iteration.
+    StopThread,
+    /// Keep going with every variable set to [`EOF_VALUE`].
+    Continue,
+}
+
+/// Options of a [`CsvDataSet`], the csv file is read by [`CsvDataSetConfig::build`].
+#[derive(Debug, Clone)]
+pub struct CsvDataSetConfig {
+    path: PathBuf,
+    variable_names: Vec<String>,
+    ignore_first_line: bool,
+    delimiter: u8,
+    quote: Option<u8>,
+    share_mode: ShareMode,
+    on_eof: OnEof,
+}
+
+impl CsvDataSetConfig {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            variable_names: Vec::new(),
+            ignore_first_line: false,
+            delimiter: b',',
+            quote: Some(b'"'),
+            share_mode: ShareMode::default(),
+            on_eof: OnEof::default(),
+        }
+    }
+
+    /// Names of the columns. When none are given, the first row holds them.
+    pub fn variable_names(mut self, names: &[&str]) -> Self {
+        self.variable_names = names.iter().map(|n| n.to_string()).collect();
+        self
+    }
+
+    /// Skip the first row when it is a header and the names are given anyway.
+    pub fn ignore_first_line(mut self, ignore: bool) -> Self {
+        self.ignore_first_line = ignore;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Character quoting fields, `None` reads quotes as plain characters.
+    pub fn quote(mut self, quote: Option<u8>) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    pub fn share_mode(mut self, share_mode: ShareMode) -> Self {
+        self.share_mode = share_mode;
+        self
+    }
+
+    pub fn on_eof(mut self, on_eof: OnEof) -> Self {
+        self.on_eof = on_eof;
+        self
+    }
+
+    /// Read the whole file.
+    pub fn build(self) -> Result<CsvDataSet, csv::Error> {
+        let file = File::open(&self.path)?;
+        self.read(file)
+    }
+
+    fn read(self, input: impl Read) -> Result<CsvDataSet, csv::Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(self.delimiter)
+            .quoting(self.quote.is_some())
+            .quote(self.quote.unwrap_or(b'"'))
+            .from_reader(input);
+        let mut rows = reader.records()
+            .map(|r| r.map(|record| record.iter().map(str::to_string).collect::<Vec<_>>()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let names = if self.variable_names.is_empty() {
+            if rows.is_empty() { Vec::new() } else { rows.remove(0) }
+        } else {
+            if self.ignore_first_line && !rows.is_empty() {
+                rows.remove(0);
+            }
+            self.variable_names
+        };
+        Ok(CsvDataSet {
+            names: Arc::new(names),
+            rows: Arc::new(rows),
+            share_mode: self.share_mode,
+            on_eof: self.on_eof,
+            cursor: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
+/// Rows of a csv file, see [`crate::group::ThreadGroup::csv_data_set`]. Before
+/// each iteration, the next row is set in the variables of the virtual user,
+/// `${name}` for each column.
+#[derive(Debug, Clone)]
+pub struct CsvDataSet {
+    names: Arc<Vec<String>>,
+    rows: Arc<Vec<Vec<String>>>,
+    share_mode: ShareMode,
+    on_eof: OnEof,
+    cursor: Arc<AtomicUsize>,
+}
+
+impl CsvDataSet {
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Go back to the first row, for a new run of the group.
+    pub(crate) fn rewind(&self) {
+        self.cursor.store(0, Ordering::Relaxed);
+    }
+
+    /// Set the next row in `vars`, `cursor` being the position of the thread
+    /// in [`ShareMode::Thread`]. `false` when the thread has to stop.
+    pub(crate) fn next_row(&self, cursor: &mut usize, vars: &mut Variables) -> bool {
+        let index = match self.share_mode {
+            ShareMode::Group => self.cursor.fetch_add(1, Ordering::Relaxed),
+            ShareMode::Thread => {
+                *cursor += 1;
+                *cursor - 1
+            },
+        };
+        let row = match self.on_eof {
+            OnEof::Recycle if !self.rows.is_empty() => self.rows.get(index % self.rows.len()),
+            _ => self.rows.get(index),
+        };
+        match row {
+            Some(row) => {
+                for (i, name) in self.names.iter().enumerate() {
+                    vars.set(name, row.get(i).map(String::as_str).unwrap_or(""));
+                }
+                true
+            },
+            None if self.on_eof == OnEof::Continue => {
+                self.names.iter().for_each(|name| vars.set(name, EOF_VALUE));
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Set the next row of every data set, `false` when one of them stops the thread.
+pub(crate) fn next_rows(data_sets: &[CsvDataSet], cursors: &mut [usize], vars: &mut Variables) -> bool {
+    data_sets.iter()
+        .zip(cursors.iter_mut())
+        .all(|(data_set, cursor)| data_set.next_row(cursor, vars))
+}
+
+#[cfg(test)]
+mod csv_tests {
+    use crate::variables::Variables;
+
+    use super::{CsvDataSetConfig, OnEof, ShareMode, EOF_VALUE};
+
+    #[test]
+    fn read_header_and_quotes() {
+        let input = "email;password\n\"liu;dao@testops.vip\";123\nbob@testops.vip;\"p\"\"w\"\n";
+        let data = CsvDataSetConfig::new("users.csv").delimiter(b';').read(input.as_bytes()).unwrap();
+        assert_eq!(data.names(), ["email", "password"]);
+        assert_eq!(data.len(), 2);
+
+        let mut vars = Variables::new();
+        let mut cursor = 0;
+        assert!(data.next_row(&mut cursor, &mut vars));
+        assert_eq!(vars.get("email"), Some("liu;dao@testops.vip"));
+        assert!(data.next_row(&mut cursor, &mut vars));
+        assert_eq!(vars.get("password"), Some("p\"w"));
+        assert!(data.next_row(&mut cursor, &mut vars));
+        assert_eq!(vars.get("email"), Some("liu;dao@testops.vip"));
+    }
+
+    #[test]
+    fn given_names_and_eof() {
+        let input = "id,name\n1,a\n2\n";
+        let data = CsvDataSetConfig::new("ids.csv")
+            .variable_names(&["uid", "uname"])
+            .ignore_first_line(true)
+            .share_mode(ShareMode::Thread)
+            .on_eof(OnEof::StopThread)
+            .read(input.as_bytes())
+            .unwrap();
+        let mut vars = Variables::new();
+        let mut cursor = 0;
+        assert!(data.next_row(&mut cursor, &mut vars));
+        assert_eq!((vars.get("uid"), vars.get("uname")), (Some("1"), Some("a")));
+        assert!(data.next_row(&mut cursor, &mut vars));
+        assert_eq!((vars.get("uid"), vars.get("uname")), (Some("2"), Some("")));
+        assert!(!data.next_row(&mut cursor, &mut vars));
+        // another thread starts from the first row
+        assert!(data.next_row(&mut 0, &mut vars));
+        assert_eq!(vars.get("uid"), Some("1"));
+
+        let data = CsvDataSetConfig::new("ids.csv").quote(None).on_eof(OnEof::Continue).read("\"x\"\n1\n".as_bytes()).unwrap();
+        assert!(data.next_row(&mut 0, &mut vars));
+        assert_eq!(vars.get("\"x\""), Some("1"));
+        assert!(data.next_row(&mut 0, &mut vars));
+        assert_eq!(vars.get("\"x\""), Some(EOF_VALUE));
+    }
+
+    #[test]
+    fn share_cursor_between_threads() {
+        let data = CsvDataSetConfig::new("ids.csv").read("id\n1\n2\n3\n".as_bytes()).unwrap();
+        let other = data.clone();
+        let (mut first, mut second) = (Variables::new(), Variables::new());
+        data.next_row(&mut 0, &mut first);
+        other.next_row(&mut 0, &mut second);
+        assert_eq!((first.get("id"), second.get("id")), (Some("1"), Some("2")));
+        data.next_row(&mut 0, &mut first);
+        other.next_row(&mut 0, &mut second);
+        assert_eq!((first.get("id"), second.get("id")), (Some("3"), Some("1")));
+    }
+}
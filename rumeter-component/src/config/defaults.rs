@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use encoding_rs::Encoding;
+use reqwest::Url;
+use tracing::*;
+
+use crate::{context::VUContext, samplers::{client::Timeouts, proxy::ProxyConfig}};
+
+/// Defaults of the requests of a virtual user, see [`crate::group::ThreadGroup::http_defaults`].
+///
+/// Scheme, host, base path and query values may be `${...}` templates, so one
+/// `env` entry of the plan can switch the whole script to another environment.
+#[derive(Debug, Clone, Default)]
+pub struct HttpDefaults {
+    scheme: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    base_path: Option<String>,
+    query: Vec<(String, String)>,
+    timeouts: Timeouts,
+    encoding: Option<&'static Encoding>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl HttpDefaults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `http` unless set.
+    pub fn scheme(mut self, scheme: &str) -> Self {
+        self.scheme = Some(scheme.to_string());
+        self
+    }
+
+    /// Host of relative urls, it may carry the port like `staging.local:8080`.
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    /// Port of relative urls, over the one `host` may carry.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Path put in front of the path of relative urls.
+    pub fn base_path(mut self, base_path: &str) -> Self {
+        self.base_path = Some(base_path.to_string());
+        self
+    }
+
+    /// A query parameter added to every url that does not have it yet.
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Time given to a whole request, see [`Timeouts::total`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = self.timeouts.total(timeout);
+        self
+    }
+
+    /// See [`Timeouts::connect`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = self.timeouts.connect(timeout);
+        self
+    }
+
+    /// See [`Timeouts::response`].
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = self.timeouts.response(timeout);
+        self
+    }
+
+    /// Charset of the request bodies, and of the response bodies not giving
+    /// one in their `Content-Type`. A label like `utf-8` or `iso-8859-1`.
+    pub fn encoding(mut self, label: &str) -> Self {
+        self.encoding = Encoding::for_label(label.as_bytes());
+        if self.encoding.is_none() {
+            warn!("unknown encoding {}, utf-8 is used", label);
+        }
+        self
+    }
+
+    /// Proxy of the samplers not setting their own.
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Timeouts of the samplers not setting their own.
+    pub fn get_timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    pub fn get_encoding(&self) -> Option<&'static Encoding> {
+        self.encoding
+    }
+
+    pub fn get_proxy(&self) -> Option<&ProxyConfig> {
+        self.proxy.as_ref()
+    }
+
+    /// Complete the rendered `url` of a sampler. A relative url gets the scheme,
+    /// host, port and base path, a url starting with `host:port` gets the scheme.
+    /// The missing query parameters are only added to the urls on the default
+    /// host, they are not sent to other services. Without a host, a relative url
+    /// is returned as it is.
+    pub fn url(&self, url: &str, ctx: &mut VUContext) -> String {
+        let host = self.host.as_ref().map(|h| ctx.render(h));
+        let scheme = self.scheme.as_ref().map(|s| ctx.render(s)).unwrap_or_else(|| "http".to_string());
+        let joined = if has_scheme(url) {
+            url.to_string()
+        } else if has_authority(url) {
+            format!("{}://{}", scheme, url)
+        } else {
+            let host = match &host {
+                Some(host) => host,
+                None => return url.to_string(),
+            };
+            let authority = match self.port {
+                Some(port) => format!("{}:{}", strip_port(host), port),
+                None => host.clone(),
+            };
+            let base_path = self.base_path.as_ref().map(|p| ctx.render(p)).unwrap_or_default();
+            format!("{}://{}{}", scheme, authority, join_path(&base_path, url))
+        };
+        let mut full = match Url::parse(&joined) {
+            Ok(full) => full,
+            Err(e) => {
+                warn!("cannot build url {}: {}", joined, e);
+                return joined;
+            },
+        };
+        let default_host = host.and_then(|h| Url::parse(&format!("{}://{}", scheme, h)).ok());
+        if default_host.is_none() || default_host.as_ref().and_then(Url::host) != full.host() {
+            return full.to_string();
+        }
+        let missing: Vec<(String, String)> = self.query.iter()
+            .filter(|(name, _)| !full.query_pairs().any(|(n, _)| n == name.as_str()))
+            .map(|(name, value)| (name.clone(), ctx.render(value)))
+            .collect();
+        if !missing.is_empty() {
+            full.query_pairs_mut().extend_pairs(missing);
+        }
+        full.to_string()
+    }
+
+    /// A request body in the default charset.
+    pub(crate) fn encode_body(&self, body: String) -> Vec<u8> {
+        match self.encoding {
+            Some(encoding) => encoding.encode(&body).0.into_owned(),
+            None => body.into_bytes(),
+        }
+    }
+}
+
+/// Whether `url` starts with a scheme, like `https://`.
+fn has_scheme(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    })
+}
+
+/// Whether `url` starts with a host and a port, like `staging.local:8080/login`.
+fn has_authority(url: &str) -> bool {
+    let authority = url.split(['/', '?', '#']).next().unwrap_or("");
+    authority.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty() && !port.is_empty() && port.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+/// `host` without the port it may carry, like `[::1]` of `[::1]:8443`.
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+fn join_path(base: &str, path: &str) -> String {
+    let base = base.trim_end_matches('/');
+    let base = if base.is_empty() || base.starts_with('/') { base.to_string() } else { format!("/{}", base) };
+    if path.is_empty() {
+        format!("{}/", base)
+    } else if path.starts_with('/') || path.starts_with('?') {
+        format!("{}{}", base, path)
+    } else {
+        format!("{}/{}", base, path)
+    }
+}
+
+#[cfg(test)]
+mod defaults_tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::context::VUContext;
+
+    use super::HttpDefaults;
+
+    #[test]
+    fn complete_relative_urls() {
+        let mut env = HashMap::new();
+        env.insert("host".to_string(), json!("staging.local:8443"));
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        ctx.set_env(env);
+        let defaults = HttpDefaults::new().scheme("https").host("${host}").base_path("api/").query("lang", "en");
+
+        assert_eq!(defaults.url("/login", &mut ctx), "https://staging.local:8443/api/login?lang=en");
+        assert_eq!(defaults.url("users?id=1&lang=fr", &mut ctx), "https://staging.local:8443/api/users?id=1&lang=fr");
+        assert_eq!(defaults.url("go?to=http://other.org", &mut ctx), "https://staging.local:8443/api/go?to=http://other.org&lang=en");
+        assert_eq!(defaults.url("http://staging.local/a", &mut ctx), "http://staging.local/a?lang=en");
+        assert_eq!(defaults.url("staging.local:9000/a", &mut ctx), "https://staging.local:9000/a?lang=en");
+        // the query does not leak to other hosts
+        assert_eq!(defaults.url("http://other.org/a", &mut ctx), "http://other.org/a");
+        assert_eq!(defaults.url("other.org:8080/a", &mut ctx), "https://other.org:8080/a");
+
+        let defaults = HttpDefaults::new().host("localhost").port(8080);
+        assert_eq!(defaults.url("", &mut ctx), "http://localhost:8080/");
+        // the port wins over the one of the host
+        let defaults = HttpDefaults::new().host("${host}").port(8080);
+        assert_eq!(defaults.url("/a", &mut ctx), "http://staging.local:8080/a");
+        assert_eq!(HttpDefaults::new().host("[::1]:8443").port(8080).url("/a", &mut ctx), "http://[::1]:8080/a");
+        assert_eq!(HttpDefaults::new().url("/login", &mut ctx), "/login");
+        assert_eq!(HttpDefaults::new().url("localhost:8080", &mut ctx), "http://localhost:8080/");
+    }
+
+    #[test]
+    fn encode_body() {
+        let defaults = HttpDefaults::new().encoding("iso-8859-1");
+        assert_eq!(defaults.encode_body("café".to_string()), b"caf\xe9");
+        assert_eq!(HttpDefaults::new().encode_body("café".to_string()), "café".as_bytes());
+    }
+}
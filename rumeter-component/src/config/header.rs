@@ -0,0 +1,87 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tracing::*;
+
+/// A set of headers, values may be `${...}` templates rendered for each request.
+///
+/// The layers of a virtual user are, from the least to the most specific: the
+/// plan and the thread group (see [`crate::group::ThreadGroup::plan_header_manager`]
+/// and [`crate::group::ThreadGroup::header_manager`]), the controllers pushing
+/// one with [`crate::context::VUContext::push_headers`], then the headers of the
+/// sampler. A header set by a layer replaces all the values of that header in
+/// the layers below.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderManager {
+    headers: HeaderMap,
+}
+
+impl HeaderManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a header. Calling it again with the same name sends both values.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                self.headers.append(name, value);
+            },
+            _ => warn!("invalid header {}: {}, ignored", name, value),
+        }
+        self
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Replace in `merged` every header this manager sets.
+    pub(crate) fn merge_into(&self, merged: &mut HeaderMap) {
+        for name in self.headers.keys() {
+            merged.remove(name);
+        }
+        for (name, value) in &self.headers {
+            merged.append(name.clone(), value.clone());
+        }
+    }
+}
+
+impl From<HeaderMap> for HeaderManager {
+    fn from(headers: HeaderMap) -> Self {
+        Self { headers }
+    }
+}
+
+/// Merge `layers`, the least specific first.
+pub(crate) fn merge<'a>(layers: impl IntoIterator<Item = &'a HeaderManager>) -> HeaderMap {
+    let mut merged = HeaderMap::new();
+    for layer in layers {
+        layer.merge_into(&mut merged);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::{merge, HeaderManager};
+
+    #[test]
+    fn merge_layers() {
+        let plan = HeaderManager::new()
+            .header("User-Agent", "rumeter")
+            .header("Accept", "text/html")
+            .header("Accept", "application/xml");
+        let group = HeaderManager::new().header("Authorization", "Bearer ${token}");
+        let sampler = HeaderManager::new().header("accept", "application/json").header("bad name", "x");
+
+        let merged = merge([&plan, &group, &sampler]);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged["user-agent"], "rumeter");
+        assert_eq!(merged.get_all("accept").iter().collect::<Vec<_>>(), ["application/json"]);
+        assert_eq!(merged["authorization"], "Bearer ${token}");
+        assert!(merge([&plan, &group]).get_all("accept").iter().count() == 2);
+    }
+}
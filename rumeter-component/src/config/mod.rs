@@ -0,0 +1,4 @@
+pub mod csv;
+pub mod cookie;
+pub mod header;
+pub mod defaults;
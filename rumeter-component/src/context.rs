@@ -0,0 +1,223 @@
+use std::{any::{Any, TypeId}, collections::HashMap, sync::Arc, time::Instant};
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde_json::Value;
+
+use crate::{Sampler, record::RecordData, functions, samplers::client::{ClientConfig, HttpClient}, variables::Variables, config::{header::HeaderManager, defaults::HttpDefaults}};
+
+/// State of one virtual user. ThreadGroup creates one per thread and keeps it
+/// across loop iterations, samplers and controllers get it in `run`.
+pub struct VUContext {
+    thread_num: u32,
+    thread_name: String,
+    iteration: u64,
+    variables: Variables,
+    env: Arc<HashMap<String, Value>>,
+    client: HttpClient,
+    rng: StdRng,
+    headers: Vec<HeaderManager>,
+    http_defaults: Arc<HttpDefaults>,
+    extensions: HashMap<TypeId, Box<dyn Any + Send>>,
+    /// Records of the samples of the iteration, see [`VUContext::sample`].
+    samples: Vec<RecordData>,
+    running: Option<Running>,
+}
+
+/// A sample started by [`VUContext::sample`] and not finished yet.
+struct Running {
+    label: String,
+    time_stamp: chrono::DateTime<chrono::Local>,
+    start: Instant,
+}
+
+impl VUContext {
+    /// A context for running samplers on their own, with a default http client.
+    pub fn new(thread_num: u32, thread_name: &str) -> Self {
+        Self::with_client(thread_num, thread_name, ClientConfig::default().build(), None, Arc::default())
+    }
+
+    pub(crate) fn with_client(thread_num: u32, thread_name: &str, client: HttpClient, seed: Option<u64>, env: Arc<HashMap<String, Value>>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            thread_num,
+            thread_name: thread_name.to_string(),
+            iteration: 0,
+            variables: Variables::new(),
+            env,
+            client,
+            rng,
+            headers: Vec::new(),
+            http_defaults: Arc::default(),
+            extensions: HashMap::new(),
+            samples: Vec::new(),
+            running: None,
+        }
+    }
+
+    /// Number of the thread in its group, starting at 1.
+    pub fn thread_num(&self) -> u32 {
+        self.thread_num
+    }
+
+    pub fn thread_name(&self) -> &str {
+        &self.thread_name
+    }
+
+    /// Loop iteration being run, starting at 0.
+    pub fn iteration(&self) -> u64 {
+        self.iteration
+    }
+
+    pub(crate) fn next_iteration(&mut self) {
+        self.iteration += 1;
+        self.samples.clear();
+    }
+
+    /// Run a sampler, keeping track of it until the end of the iteration.
+    pub async fn sample<S: Sampler + Sync + ?Sized>(&mut self, sampler: &S) -> RecordData {
+        self.running = Some(Running { label: sampler.label().to_string(), time_stamp: chrono::Local::now(), start: Instant::now() });
+        let re = sampler.run(self).await;
+        self.running = None;
+        self.samples.push(re.clone());
+        re
+    }
+
+    /// Records of an iteration stopped before its end: the samples it
+    /// finished, then the one it was running.
+    pub(crate) fn interrupted_samples(&mut self) -> Vec<RecordData> {
+        let mut samples = std::mem::take(&mut self.samples);
+        if let Some(running) = self.running.take() {
+            let elapsed = running.start.elapsed().as_millis() as u64;
+            samples.push(RecordData::new(
+                running.time_stamp.timestamp_millis() as u128,
+                elapsed,
+                running.label,
+                0,
+                "Interrupted".to_string(),
+                self.thread_name.clone(),
+                "text".to_string(),
+                false,
+                Some("interrupted by the end of the test".to_string()),
+                0,
+                0,
+                0,
+                0,
+                "".to_string(),
+                elapsed,
+                0,
+                0,
+                None,
+            ));
+        }
+        samples
+    }
+
+    pub fn vars(&self) -> &Variables {
+        &self.variables
+    }
+
+    pub fn vars_mut(&mut self) -> &mut Variables {
+        &mut self.variables
+    }
+
+    /// Read-only values of the plan, like the `env` of a script node.
+    pub fn env(&self) -> &HashMap<String, Value> {
+        &self.env
+    }
+
+    pub fn set_env(&mut self, env: HashMap<String, Value>) {
+        self.env = Arc::new(env);
+    }
+
+    /// Render a `${...}` template with the variables and functions, see [`functions::render`].
+    pub fn render(&mut self, template: &str) -> String {
+        functions::render(template, self)
+    }
+
+    /// Render every string inside a json value.
+    pub fn render_json(&mut self, value: &mut Value) {
+        functions::render_json(value, self)
+    }
+
+    /// The http client of this virtual user, see [`ClientConfig`].
+    pub fn client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    pub fn set_client(&mut self, client: HttpClient) {
+        self.client = client;
+    }
+
+    /// Add a layer of headers over the current ones, until [`VUContext::pop_headers`].
+    /// Controllers use it to give headers to the samplers they run.
+    pub fn push_headers(&mut self, headers: HeaderManager) {
+        self.headers.push(headers);
+    }
+
+    /// Remove the last layer of headers.
+    pub fn pop_headers(&mut self) -> Option<HeaderManager> {
+        self.headers.pop()
+    }
+
+    /// Layers of headers, the least specific first, see [`HeaderManager`].
+    pub fn header_layers(&self) -> &[HeaderManager] {
+        &self.headers
+    }
+
+    /// What the http based samplers inherit, see [`HttpDefaults`].
+    pub fn http_defaults(&self) -> Arc<HttpDefaults> {
+        self.http_defaults.clone()
+    }
+
+    pub fn set_http_defaults(&mut self, http_defaults: HttpDefaults) {
+        self.http_defaults = Arc::new(http_defaults);
+    }
+
+    /// Random generator of this virtual user, seeded from ThreadGroup when a seed is set.
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Keep a value of any type for the rest of the virtual user's life,
+    /// replacing the previous value of the same type.
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.extensions.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|b| *b))
+    }
+
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.extensions.get(&TypeId::of::<T>()).and_then(|v| v.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.extensions.get_mut(&TypeId::of::<T>()).and_then(|v| v.downcast_mut())
+    }
+
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.extensions.remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok().map(|b| *b))
+    }
+}
+
+#[cfg(test)]
+mod context_tests {
+    use super::VUContext;
+
+    struct Session(String);
+
+    #[test]
+    fn keep_session_state() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        assert!(ctx.get::<Session>().is_none());
+        ctx.insert(Session("first".to_string()));
+        ctx.get_mut::<Session>().unwrap().0.push_str("-changed");
+        assert_eq!(ctx.get::<Session>().unwrap().0, "first-changed");
+        let old = ctx.insert(Session("second".to_string())).unwrap();
+        assert_eq!(old.0, "first-changed");
+        assert_eq!(ctx.remove::<Session>().unwrap().0, "second");
+        assert!(ctx.get::<Session>().is_none());
+    }
+}
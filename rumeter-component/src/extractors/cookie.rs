@@ -0,0 +1,36 @@
+use crate::{Extractor, record::RecordData, context::VUContext};
+
+/// Stores the value of a cookie the virtual user holds for the url of the sample,
+/// see [`crate::config::cookie::CookieManager`].
+#[derive(Clone, Debug)]
+pub struct CookieExtractor {
+    name: String,
+    cookie: String,
+    default: Option<String>,
+}
+
+impl CookieExtractor {
+    pub fn new(name: &str, cookie: &str) -> Self {
+        Self { name: name.to_string(), cookie: cookie.to_string(), default: None }
+    }
+
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+}
+
+impl Extractor for CookieExtractor {
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext) {
+        let value = data.response_result().and_then(|r| r.cookie(&self.cookie));
+        match (value, &self.default) {
+            (Some(v), _) => ctx.vars_mut().set(&self.name, v),
+            (None, Some(d)) => ctx.vars_mut().set(&self.name, d.as_str()),
+            (None, None) => {},
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+}
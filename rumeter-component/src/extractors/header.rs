@@ -0,0 +1,35 @@
+use crate::{Extractor, record::RecordData, context::VUContext};
+
+/// Stores the value of a response header, the name is case-insensitive.
+#[derive(Clone, Debug)]
+pub struct HeaderExtractor {
+    name: String,
+    header: String,
+    default: Option<String>,
+}
+
+impl HeaderExtractor {
+    pub fn new(name: &str, header: &str) -> Self {
+        Self { name: name.to_string(), header: header.to_string(), default: None }
+    }
+
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+}
+
+impl Extractor for HeaderExtractor {
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext) {
+        let value = data.response_result().and_then(|r| r.header(&self.header));
+        match (value, &self.default) {
+            (Some(v), _) => ctx.vars_mut().set(&self.name, v),
+            (None, Some(d)) => ctx.vars_mut().set(&self.name, d.as_str()),
+            (None, None) => {},
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,105 @@
+use scraper::{Html, Selector};
+use sxd_xpath::{Factory, Context, Value};
+
+use crate::{Extractor, record::RecordData, context::VUContext};
+
+use super::{store, MatchNo};
+
+/// CSS Selector Extractor for html responses. Stores the text of the matched
+/// elements, or one of their attributes.
+#[derive(Clone, Debug)]
+pub struct CssExtractor {
+    name: String,
+    selector: Selector,
+    attribute: Option<String>,
+    match_no: MatchNo,
+    default: Option<String>,
+}
+
+impl CssExtractor {
+    pub fn new(name: &str, selector: &str) -> Result<Self, String> {
+        let selector = Selector::parse(selector).map_err(|e| e.to_string())?;
+        Ok(Self { name: name.to_string(), selector, attribute: None, match_no: MatchNo::default(), default: None })
+    }
+
+    pub fn attribute(mut self, attribute: &str) -> Self {
+        self.attribute = Some(attribute.to_string());
+        self
+    }
+
+    pub fn match_no(mut self, match_no: MatchNo) -> Self {
+        self.match_no = match_no;
+        self
+    }
+
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+}
+
+impl Extractor for CssExtractor {
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext) {
+        let body = data.response_result().map(|r| r.response_data()).unwrap_or("");
+        let doc = Html::parse_document(body);
+        let matches = doc.select(&self.selector)
+            .filter_map(|el| match &self.attribute {
+                Some(attr) => el.value().attr(attr).map(str::to_string),
+                None => Some(el.text().collect::<String>().trim().to_string()),
+            })
+            .collect();
+        store(ctx, &self.name, matches, self.match_no, self.default.as_deref());
+    }
+}
+
+/// XPath Extractor. The response must be well-formed XML or XHTML.
+#[derive(Clone, Debug)]
+pub struct XPathExtractor {
+    name: String,
+    xpath: String,
+    match_no: MatchNo,
+    default: Option<String>,
+}
+
+impl XPathExtractor {
+    pub fn new(name: &str, xpath: &str) -> Result<Self, String> {
+        // compiled again on every use, the compiled form cannot be shared between threads
+        compile(xpath)?;
+        Ok(Self { name: name.to_string(), xpath: xpath.to_string(), match_no: MatchNo::default(), default: None })
+    }
+
+    pub fn match_no(mut self, match_no: MatchNo) -> Self {
+        self.match_no = match_no;
+        self
+    }
+
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+
+    fn evaluate(&self, body: &str) -> Result<Vec<String>, String> {
+        let package = sxd_document::parser::parse(body).map_err(|e| e.to_string())?;
+        let doc = package.as_document();
+        let xpath = compile(&self.xpath)?;
+        let value = xpath.evaluate(&Context::new(), doc.root()).map_err(|e| e.to_string())?;
+        Ok(match value {
+            Value::Nodeset(nodes) => nodes.document_order().iter().map(|n| n.string_value()).collect(),
+            other => vec![other.string()],
+        })
+    }
+}
+
+fn compile(xpath: &str) -> Result<sxd_xpath::XPath, String> {
+    Factory::new().build(xpath)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "empty xpath".to_string())
+}
+
+impl Extractor for XPathExtractor {
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext) {
+        let body = data.response_result().map(|r| r.response_data()).unwrap_or("");
+        let matches = self.evaluate(body).unwrap_or_default();
+        store(ctx, &self.name, matches, self.match_no, self.default.as_deref());
+    }
+}
@@ -0,0 +1,48 @@
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+use crate::{Extractor, record::RecordData, context::VUContext};
+
+use super::{store, MatchNo};
+
+/// Stores the values matched by a JSONPath expression on the response body.
+/// Strings are stored without quotes, other values as json.
+#[derive(Clone, Debug)]
+pub struct JsonPathExtractor {
+    name: String,
+    path: JsonPath,
+    match_no: MatchNo,
+    default: Option<String>,
+}
+
+impl JsonPathExtractor {
+    pub fn new(name: &str, path: &str) -> Result<Self, serde_json_path::ParseError> {
+        Ok(Self { name: name.to_string(), path: JsonPath::parse(path)?, match_no: MatchNo::default(), default: None })
+    }
+
+    pub fn match_no(mut self, match_no: MatchNo) -> Self {
+        self.match_no = match_no;
+        self
+    }
+
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+}
+
+impl Extractor for JsonPathExtractor {
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext) {
+        let body = data.response_result().map(|r| r.response_data()).unwrap_or("");
+        let matches = match serde_json::from_str::<Value>(body) {
+            Ok(json) => self.path.query(&json).all().into_iter()
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        store(ctx, &self.name, matches, self.match_no, self.default.as_deref());
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{Extractor, record::RecordData, context::VUContext};
+
+pub mod json_path;
+pub mod regex;
+pub mod html;
+pub mod header;
+pub mod cookie;
+
+pub type ExtractorRef = Arc<dyn Extractor>;
+
+/// Run every extractor against `data`, writing into the variables of `ctx`.
+pub fn apply(extractors: &[ExtractorRef], data: &RecordData, ctx: &mut VUContext) {
+    for e in extractors {
+        e.extract(data, ctx);
+    }
+}
+
+/// Which match is kept, the "Match No." of JMeter extractors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchNo {
+    /// a random match.
+    Random,
+    /// the n-th match, starting at 1.
+    Nth(usize),
+    /// every match, stored as `name_1` .. `name_n` with the count in `name_matchNr`.
+    All,
+}
+
+impl From<i32> for MatchNo {
+    /// 0 is random, a negative number is all, like JMeter.
+    fn from(n: i32) -> Self {
+        match n {
+            0 => MatchNo::Random,
+            n if n > 0 => MatchNo::Nth(n as usize),
+            _ => MatchNo::All,
+        }
+    }
+}
+
+impl Default for MatchNo {
+    fn default() -> Self {
+        MatchNo::Nth(1)
+    }
+}
+
+/// Write `matches` to the variables of `ctx` under `name`, a random match being
+/// picked with [`VUContext::rng`]. When nothing fits `match_no`, `name` gets
+/// `default`, or keeps its previous value if there is no default.
+pub fn store(ctx: &mut VUContext, name: &str, matches: Vec<String>, match_no: MatchNo, default: Option<&str>) {
+    let picked = match match_no {
+        MatchNo::Nth(n) => matches.get(n.wrapping_sub(1)).cloned(),
+        MatchNo::Random if !matches.is_empty() => {
+            let i = ctx.rng().gen_range(0..matches.len());
+            Some(matches[i].clone())
+        },
+        MatchNo::Random => None,
+        MatchNo::All => {
+            let vars = ctx.vars_mut();
+            let count_name = format!("{}_matchNr", name);
+            let old_count: usize = vars.get(&count_name).and_then(|c| c.parse().ok()).unwrap_or(0);
+            for i in 1..=old_count {
+                vars.remove(&format!("{}_{}", name, i));
+            }
+            vars.set(&count_name, matches.len().to_string());
+            for (i, m) in matches.into_iter().enumerate() {
+                vars.set(&format!("{}_{}", name, i + 1), m);
+            }
+            None
+        },
+    };
+    match (picked, default) {
+        (Some(value), _) => ctx.vars_mut().set(name, value),
+        (None, Some(default)) => ctx.vars_mut().set(name, default),
+        (None, None) => {},
+    }
+}
+
+#[cfg(test)]
+mod extractors_tests {
+    use std::collections::HashMap;
+
+    use crate::{Extractor, context::VUContext, record::{RecordData, ResponseResult}, samplers::client::ClientConfig};
+
+    use super::{MatchNo, json_path::JsonPathExtractor, regex::RegexExtractor, html::{CssExtractor, XPathExtractor}, header::HeaderExtractor, cookie::CookieExtractor};
+
+    fn record(body: &str) -> RecordData {
+        let mut headers = HashMap::new();
+        headers.insert("x-request-id".to_string(), "req-1".to_string());
+        RecordData::new(
+            0, 10, "login".to_string(), 200, "OK".to_string(), "".to_string(), "text".to_string(),
+            true, None, 0, 0, 0, 0, "http://localhost/login".to_string(), 0, 0, 0,
+            Some(ResponseResult::new(headers, body.to_string()).with_cookies(HashMap::from([("sid".to_string(), "s-1".to_string())]))),
+        )
+    }
+
+    #[test]
+    fn extract_json_and_header() {
+        let re = record(r#"{"data": {"token": "abc", "ids": [3, 5, 8]}}"#);
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        JsonPathExtractor::new("token", "$.data.token").unwrap().extract(&re, &mut ctx);
+        JsonPathExtractor::new("id", "$.data.ids[*]").unwrap().match_no(MatchNo::All).extract(&re, &mut ctx);
+        JsonPathExtractor::new("missing", "$.data.none").unwrap().default("NOT_FOUND").extract(&re, &mut ctx);
+        HeaderExtractor::new("rid", "X-Request-Id").extract(&re, &mut ctx);
+        CookieExtractor::new("session", "sid").extract(&re, &mut ctx);
+        CookieExtractor::new("other", "none").default("NO_COOKIE").extract(&re, &mut ctx);
+        assert_eq!(ctx.vars().get("token"), Some("abc"));
+        assert_eq!(ctx.vars().get("id_matchNr"), Some("3"));
+        assert_eq!(ctx.vars().get("id_3"), Some("8"));
+        assert_eq!(ctx.vars().get("missing"), Some("NOT_FOUND"));
+        assert_eq!(ctx.vars().get("rid"), Some("req-1"));
+        assert_eq!(ctx.vars().get("session"), Some("s-1"));
+        assert_eq!(ctx.vars().get("other"), Some("NO_COOKIE"));
+    }
+
+    #[test]
+    fn extract_regex() {
+        let re = record("id=3;id=5;id=8");
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        RegexExtractor::new("second", r"id=(\d)").unwrap().match_no(MatchNo::Nth(2)).extract(&re, &mut ctx);
+        RegexExtractor::new("pair", r"(id)=(\d)").unwrap().template("$2$-$1$").extract(&re, &mut ctx);
+        assert_eq!(ctx.vars().get("second"), Some("5"));
+        assert_eq!(ctx.vars().get("pair"), Some("3-id"));
+    }
+
+    #[test]
+    fn extract_html() {
+        let re = record(r#"<html><body><form><input name="csrf" value="t0k3n"/></form><a href="/next">next</a></body></html>"#);
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        CssExtractor::new("csrf", "input[name=csrf]").unwrap().attribute("value").extract(&re, &mut ctx);
+        CssExtractor::new("link", "a").unwrap().extract(&re, &mut ctx);
+        XPathExtractor::new("href", "//a/@href").unwrap().extract(&re, &mut ctx);
+        assert_eq!(ctx.vars().get("csrf"), Some("t0k3n"));
+        assert_eq!(ctx.vars().get("link"), Some("next"));
+        assert_eq!(ctx.vars().get("href"), Some("/next"));
+    }
+
+    #[test]
+    fn pick_random_match_with_the_user_seed() {
+        let re = record("id=1;id=2;id=3;id=4;id=5;id=6;id=7;id=8;id=9");
+        let extractor = RegexExtractor::new("id", r"id=(\d)").unwrap().match_no(MatchNo::Random);
+        let picks = |seed| {
+            let mut ctx = VUContext::with_client(1, "Thread Group 1-1", ClientConfig::default().build(), Some(seed), Default::default());
+            (0..10).map(|_| {
+                extractor.extract(&re, &mut ctx);
+                ctx.vars().get("id").unwrap().to_string()
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+    }
+}
@@ -0,0 +1,93 @@
+use regex::Regex;
+
+use crate::{Extractor, record::RecordData, context::VUContext};
+
+use super::{store, MatchNo};
+
+/// Part of the sample a [`RegexExtractor`] looks into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Body,
+    /// response headers, one `name: value` per line.
+    Headers,
+    Url,
+    ResponseCode,
+    ResponseMessage,
+}
+
+/// Regular Expression Extractor. Each match is rendered with a template where
+/// `$n$` is replaced by the n-th group, `$1$` by default.
+#[derive(Clone, Debug)]
+pub struct RegexExtractor {
+    name: String,
+    regex: Regex,
+    template: String,
+    field: Field,
+    match_no: MatchNo,
+    default: Option<String>,
+}
+
+impl RegexExtractor {
+    pub fn new(name: &str, regex: &str) -> Result<Self, regex::Error> {
+        let regex = Regex::new(regex)?;
+        let template = if regex.captures_len() > 1 { "$1$" } else { "$0$" };
+        Ok(Self {
+            name: name.to_string(),
+            regex,
+            template: template.to_string(),
+            field: Field::Body,
+            match_no: MatchNo::default(),
+            default: None,
+        })
+    }
+
+    pub fn template(mut self, template: &str) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    pub fn field(mut self, field: Field) -> Self {
+        self.field = field;
+        self
+    }
+
+    pub fn match_no(mut self, match_no: MatchNo) -> Self {
+        self.match_no = match_no;
+        self
+    }
+
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+
+    fn render(&self, caps: &regex::Captures) -> String {
+        let mut out = self.template.clone();
+        // replace the higher groups first so $1$ does not eat into $10$
+        for i in (0..caps.len()).rev() {
+            let value = caps.get(i).map(|m| m.as_str()).unwrap_or("");
+            out = out.replace(&format!("${}$", i), value);
+        }
+        out
+    }
+}
+
+impl Extractor for RegexExtractor {
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext) {
+        let text = match self.field {
+            Field::Body => data.response_result().map(|r| r.response_data().to_string()).unwrap_or_default(),
+            Field::Headers => data.response_result()
+                .map(|r| r.headers().iter().map(|(k, v)| format!("{}: {}\n", k, v)).collect())
+                .unwrap_or_default(),
+            Field::Url => data.get_url().to_string(),
+            Field::ResponseCode => data.get_response_code().to_string(),
+            Field::ResponseMessage => data.get_response_message().to_string(),
+        };
+        let matches = self.regex.captures_iter(&text).map(|caps| self.render(&caps)).collect();
+        store(ctx, &self.name, matches, self.match_no, self.default.as_deref());
+    }
+
+    fn reads_body(&self) -> bool {
+        self.field == Field::Body
+    }
+}
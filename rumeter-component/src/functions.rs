@@ -0,0 +1,300 @@
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+
+use rand::Rng;
+use serde_json::Value;
+
+use crate::context::VUContext;
+
+/// Counter of `${__counter(FALSE)}`, shared by the virtual users of a run of a
+/// ThreadGroup, see [`crate::group::ThreadGroup::start`].
+#[derive(Clone, Default)]
+pub(crate) struct SharedCounter(Arc<AtomicU64>);
+
+/// Counter of `${__counter(TRUE)}`, kept in the context of each virtual user.
+struct UserCounter(u64);
+
+/// Render `template`. `${name}` is looked up in the variables, then in the env
+/// of the plan; `${__fn(args)}` calls a function. Arguments are split on
+/// commas, `\,` keeps a comma, and may contain templates themselves. Unknown
+/// references are kept as they are.
+pub fn render(template: &str, ctx: &mut VUContext) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match closing_brace(after) {
+            Some(end) => {
+                let inner = &after[..end];
+                match resolve(inner, ctx) {
+                    Some(value) => out.push_str(&value),
+                    None => out.push_str(&rest[start..start + 2 + end + 1]),
+                }
+                rest = &after[end + 1..];
+            },
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Render every string inside a json value, keys excluded. A string that is a
+/// single template, like `"${id}"`, becomes the json its value parses to when
+/// that is not a string, so `7` is sent as a number and `[1,2]` as an array.
+pub fn render_json(value: &mut Value, ctx: &mut VUContext) {
+    match value {
+        Value::String(s) => {
+            let rendered = render(s, ctx);
+            let whole = s.starts_with("${") && closing_brace(&s[2..]) == Some(s.len() - 3);
+            *value = match serde_json::from_str::<Value>(&rendered) {
+                Ok(typed) if whole && !typed.is_string() => typed,
+                _ => Value::String(rendered),
+            };
+        },
+        Value::Array(items) => items.iter_mut().for_each(|v| render_json(v, ctx)),
+        Value::Object(map) => map.values_mut().for_each(|v| render_json(v, ctx)),
+        _ => {},
+    }
+}
+
+/// Position of the `}` closing a `${`, skipping nested templates.
+fn closing_brace(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut depth = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'$' && bytes.get(i + 1) == Some(&b'{') {
+            depth += 1;
+            i += 1;
+        } else if bytes[i] == b'}' {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+fn resolve(inner: &str, ctx: &mut VUContext) -> Option<String> {
+    if !inner.starts_with("__") {
+        return ctx.vars().get(inner).map(str::to_string)
+            .or_else(|| ctx.env().get(inner).map(value_to_string));
+    }
+    let (name, args) = match inner.find('(') {
+        Some(open) if inner.ends_with(')') => (&inner[..open], split_args(&inner[open + 1..inner.len() - 1])),
+        Some(_) => return None,
+        None => (inner, Vec::new()),
+    };
+    let args: Vec<String> = args.iter().map(|a| render(a, ctx)).collect();
+    call(name, &args, ctx)
+}
+
+/// Split on top level commas, `\,` being a literal comma.
+fn split_args(s: &str) -> Vec<String> {
+    if s.is_empty() {
+        return Vec::new();
+    }
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => current.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'{') => {
+                depth += 1;
+                current.push(c);
+            },
+            '}' if depth > 0 => {
+                depth -= 1;
+                current.push(c);
+            },
+            ',' if depth == 0 => args.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    args.push(current);
+    args
+}
+
+fn call(name: &str, args: &[String], ctx: &mut VUContext) -> Option<String> {
+    let arg = |i: usize| args.get(i).map(|a| a.trim()).filter(|a| !a.is_empty());
+    let (value, var) = match name {
+        "__Random" => {
+            let min: i64 = arg(0)?.parse().ok()?;
+            let max: i64 = arg(1)?.parse().ok()?;
+            if min > max {
+                return None;
+            }
+            (ctx.rng().gen_range(min..=max).to_string(), arg(2))
+        },
+        "__UUID" => {
+            let bytes: [u8; 16] = ctx.rng().gen();
+            (uuid::Builder::from_random_bytes(bytes).into_uuid().to_string(), None)
+        },
+        "__time" => {
+            let now = chrono::Local::now();
+            let value = match arg(0) {
+                None => now.timestamp_millis().to_string(),
+                Some("/1000") => now.timestamp().to_string(),
+                Some(format) => now.format(&java_date_format(format)).to_string(),
+            };
+            (value, arg(1))
+        },
+        "__counter" => {
+            let per_user = arg(0).map(|a| a.eq_ignore_ascii_case("true")).unwrap_or(false);
+            let value = if per_user {
+                let counter = match ctx.get_mut::<UserCounter>() {
+                    Some(c) => c,
+                    None => {
+                        ctx.insert(UserCounter(0));
+                        ctx.get_mut::<UserCounter>().unwrap()
+                    },
+                };
+                counter.0 += 1;
+                counter.0
+            } else {
+                let counter = match ctx.get::<SharedCounter>() {
+                    Some(c) => c,
+                    None => {
+                        ctx.insert(SharedCounter::default());
+                        ctx.get::<SharedCounter>().unwrap()
+                    },
+                };
+                counter.0.fetch_add(1, Ordering::Relaxed) + 1
+            };
+            (value.to_string(), arg(1))
+        },
+        "__threadNum" => (ctx.thread_num().to_string(), None),
+        "__env" => {
+            let key = arg(0)?;
+            let value = ctx.env().get(key).map(value_to_string)
+                .or_else(|| std::env::var(key).ok())
+                .or_else(|| arg(1).map(str::to_string))?;
+            (value, arg(2))
+        },
+        _ => return None,
+    };
+    if let Some(var) = var {
+        let var = var.to_string();
+        ctx.vars_mut().set(&var, value.as_str());
+    }
+    Some(value)
+}
+
+fn value_to_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Translate a java SimpleDateFormat pattern, as used by JMeter, to chrono.
+/// Text between single quotes is kept literally.
+fn java_date_format(format: &str) -> String {
+    let chars: Vec<char> = format.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != '\'' {
+                push_literal(&mut out, chars[i]);
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+        let mut run = 1;
+        while i + run < chars.len() && chars[i + run] == c {
+            run += 1;
+        }
+        let spec = match (c, run) {
+            ('y', 2) => "%y",
+            ('y', _) => "%Y",
+            ('M', 1) | ('M', 2) => "%m",
+            ('M', 3) => "%b",
+            ('M', _) => "%B",
+            ('d', _) => "%d",
+            ('H', _) => "%H",
+            ('h', _) => "%I",
+            ('m', _) => "%M",
+            ('s', _) => "%S",
+            ('S', _) => "%3f",
+            ('a', _) => "%p",
+            ('E', 1..=3) => "%a",
+            ('E', _) => "%A",
+            ('Z', _) => "%z",
+            _ => {
+                (0..run).for_each(|_| push_literal(&mut out, c));
+                i += run;
+                continue;
+            },
+        };
+        out.push_str(spec);
+        i += run;
+    }
+    out
+}
+
+fn push_literal(out: &mut String, c: char) {
+    if c == '%' {
+        out.push_str("%%");
+    } else {
+        out.push(c);
+    }
+}
+
+#[cfg(test)]
+mod functions_tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::context::VUContext;
+
+    use super::{render, render_json, java_date_format};
+
+    #[test]
+    fn render_functions() {
+        let mut env = HashMap::new();
+        env.insert("host".to_string(), json!("staging.local"));
+        let mut ctx = VUContext::new(3, "Thread Group 1-3");
+        ctx.set_env(env);
+        ctx.vars_mut().set("max", "5");
+
+        assert_eq!(render("http://${host}/t/${__threadNum}", &mut ctx), "http://staging.local/t/3");
+        let n: i64 = render("${__Random(1,${max},rnd)}", &mut ctx).parse().unwrap();
+        assert!((1..=5).contains(&n));
+        assert_eq!(ctx.vars().get("rnd"), Some(n.to_string().as_str()));
+        assert_eq!(render("${__counter(TRUE)},${__counter(TRUE)}", &mut ctx), "1,2");
+        assert_eq!(render("${__UUID()}", &mut ctx).len(), 36);
+        assert_eq!(render("${__env(RUMETER_NOT_SET,fallback\\, really)}", &mut ctx), "fallback, really");
+        assert_eq!(render("${__unknown(1)} ${missing}", &mut ctx), "${__unknown(1)} ${missing}");
+    }
+
+    #[test]
+    fn render_json_strings() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        ctx.vars_mut().set("token", "abc");
+        ctx.vars_mut().set("id", "7");
+        ctx.vars_mut().set("tags", r#"["a","b"]"#);
+        let mut body = json!({"query": "q", "variables": {"token": "${token}", "ids": ["${id}", "id ${id}", 1], "tags": "${tags}", "none": "${missing}"}});
+        render_json(&mut body, &mut ctx);
+        assert_eq!(body, json!({"query": "q", "variables": {"token": "abc", "ids": [7, "id 7", 1], "tags": ["a", "b"], "none": "${missing}"}}));
+        assert_eq!(render("open ${id", &mut ctx), "open ${id");
+    }
+
+    #[test]
+    fn translate_date_format() {
+        assert_eq!(java_date_format("yyyyMMdd"), "%Y%m%d");
+        assert_eq!(java_date_format("yyyy-MM-dd'T'HH:mm:ss.SSS"), "%Y-%m-%dT%H:%M:%S.%3f");
+    }
+}
@@ -1,29 +1,121 @@
 
-use std::{time::Duration, sync::{Arc, Mutex}};
+use std::{time::Duration, sync::{Arc, Mutex}, collections::HashMap};
 
-use crate::{record::RecordData, Output, Controller};
+use crate::{record::RecordData, Output, Controller, context::VUContext, functions::SharedCounter, samplers::client::{ClientConfig, ClientPool}, config::{csv::{self, CsvDataSet}, cookie::CookieManager, header::HeaderManager, defaults::HttpDefaults}};
+use serde_json::Value;
 use tracing::*;
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ThreadGroup {
     thread_num: u32,
     rampup: Duration,
     loop_num: i32,
     duration: Option<Duration>,
+    client_config: ClientConfig,
+    seed: Option<u64>,
+    env: Arc<HashMap<String, Value>>,
+    data_sets: Vec<CsvDataSet>,
+    cookie_manager: Option<CookieManager>,
+    plan_headers: HeaderManager,
+    headers: HeaderManager,
+    http_defaults: HttpDefaults,
 }
 
 impl ThreadGroup {
     pub fn new(thread_num: u32, rampup: Duration, loop_num: i32, duration: Option<Duration>) -> Self {
-        Self { thread_num, rampup, loop_num, duration}
+        Self {
+            thread_num,
+            rampup,
+            loop_num,
+            duration,
+            client_config: ClientConfig::default(),
+            seed: None,
+            env: Arc::default(),
+            data_sets: Vec::new(),
+            cookie_manager: None,
+            plan_headers: HeaderManager::default(),
+            headers: HeaderManager::default(),
+            http_defaults: HttpDefaults::default(),
+        }
+    }
+
+    /// Set how the http clients of the virtual users are pooled.
+    pub fn client_config(mut self, client_config: ClientConfig) -> Self {
+        self.client_config = client_config;
+        self
+    }
+
+    /// Seed the random generators of the virtual users, thread `n` uses `seed + n`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
     }
 
-    pub async fn start<C>(self: &Self, controller: C, out: Arc<Mutex<impl Output+Send + 'static>>)
+    /// Values every virtual user can reference in templates, e.g. the `env` of a script node.
+    pub fn env(mut self, env: HashMap<String, Value>) -> Self {
+        self.env = Arc::new(env);
+        self
+    }
+
+    /// Set the next row of `data_set` in the variables before each iteration.
+    pub fn csv_data_set(mut self, data_set: CsvDataSet) -> Self {
+        self.data_sets.push(data_set);
+        self
+    }
+
+    /// Give every virtual user a cookie jar, shared by its http based samplers.
+    pub fn cookie_manager(mut self, cookie_manager: CookieManager) -> Self {
+        self.cookie_manager = Some(cookie_manager);
+        self
+    }
+
+    /// Headers of the plan, below the ones of the group, see [`HeaderManager`].
+    pub fn plan_header_manager(mut self, headers: HeaderManager) -> Self {
+        self.plan_headers = headers;
+        self
+    }
+
+    /// Headers of the group, sent by every http based sampler of its virtual users.
+    pub fn header_manager(mut self, headers: HeaderManager) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Scheme, host, base path and so on of the relative urls of the samplers.
+    pub fn http_defaults(mut self, http_defaults: HttpDefaults) -> Self {
+        self.http_defaults = http_defaults;
+        self
+    }
+
+    /// Virtual user `t`, with the clients, cookies, headers and defaults of the group.
+    fn new_user(&self, t: u32, pool: &ClientPool, counter: &SharedCounter) -> VirtualUser {
+        let mut ctx = VUContext::with_client(t, &format!("Thread Group 1-{}", t), pool.client(), self.seed.map(|s| s + t as u64), self.env.clone());
+        ctx.insert(counter.clone());
+        if let Some(manager) = &self.cookie_manager {
+            ctx.insert(manager.jar());
+        }
+        ctx.push_headers(self.plan_headers.clone());
+        ctx.push_headers(self.headers.clone());
+        ctx.set_http_defaults(self.http_defaults.clone());
+        VirtualUser {
+            ctx,
+            pool: pool.clone(),
+            data_sets: self.data_sets.clone(),
+            cursors: vec![0; self.data_sets.len()],
+            cookie_manager: self.cookie_manager.clone(),
+        }
+    }
+
+    pub async fn start<C>(&self, controller: C, out: Arc<Mutex<impl Output+Send + 'static>>)
     where
         C: Controller + Send + Sync + Clone + 'static,
     {
         let (_test_record_tx, mut test_record_rx) = tokio::sync::mpsc::channel::<Vec<RecordData>>(self.thread_num.try_into().unwrap());
-        let it = self.clone().rampup / self.thread_num;
+        let it = self.rampup / self.thread_num;
+        let pool = ClientPool::new(self.client_config.clone());
+        let counter = SharedCounter::default();
+        self.data_sets.iter().for_each(CsvDataSet::rewind);
         let thread_count = Arc::new(Mutex::new(0i32));
         let (tx, _rx) = tokio::sync::broadcast::channel::<bool>(1);
         match self.duration {
@@ -34,6 +126,7 @@ impl ThreadGroup {
                     let test_record_tx = _test_record_tx.clone();
                     let ctrl = controller.clone();
                     let mut receiver = tx.subscribe();
+                    let mut user = self.new_user(t, &pool, &counter);
                     
                     tokio::spawn(async move {
                         {
@@ -41,23 +134,32 @@ impl ThreadGroup {
                             *tc += 1;
                         }
                         loop {
-                            let mut re_vec = ctrl.run().await;
+                            if !user.start_iteration() {
+                                info!("thread-{} is out of data", &t);
+                                break;
+                            }
+                            // a hung iteration must not outlive the test
+                            let mut interrupted = false;
+                            let mut re_vec = tokio::select! {
+                                re_vec = ctrl.run(&mut user.ctx) => re_vec,
+                                _ = receiver.recv() => {
+                                    info!("terminating thread-{} in the middle of an iteration", &t);
+                                    interrupted = true;
+                                    user.ctx.interrupted_samples()
+                                },
+                            };
                             {
                                 let tc = thread_count.lock().unwrap();
                                 for re in &mut re_vec {
-                                    re.grp_threads((*tc).clone() as u32);
-                                    re.all_threads((*tc).clone() as u32);
-                                    re.thread_name(format!("Thread Group 1-{}", &t));
+                                    stamp(re, *tc as u32, user.ctx.thread_name());
                                 }
                             }
                             _ = test_record_tx.send(re_vec).await;
-                            match receiver.try_recv() {
-                                Ok(_) => {
-                                    info!("terminating thread-{}", &t);
-                                    break;
-                                },
-                                Err(_) => {},
+                            if interrupted || receiver.try_recv().is_ok() {
+                                info!("terminating thread-{}", &t);
+                                break;
                             }
+                            user.end_iteration();
                         }
                         {
                             let mut tc = thread_count.lock().unwrap();
@@ -70,7 +172,7 @@ impl ThreadGroup {
                 }
                 let task1 = tokio::spawn(async move {
                     tokio::time::sleep(d).await;
-                    tx.send(true).unwrap();
+                    _ = tx.send(true);
                 });
                 let task2 = tokio::spawn(async move {
                     
@@ -82,7 +184,10 @@ impl ThreadGroup {
                 });
                 
                 drop(_test_record_tx);
-                _ = tokio::join!(task1, task2);
+                // the records end once every thread stopped, at the end of the
+                // duration or before when they ran out of data
+                _ = task2.await;
+                task1.abort();
                 
             },
             None => {
@@ -92,26 +197,28 @@ impl ThreadGroup {
                     let test_record_tx = _test_record_tx.clone();
                     let ctrl = controller.clone();
                     let loop_num = self.loop_num;
-                    tokio::spawn(async move{
+                    let mut user = self.new_user(t, &pool, &counter);
+                    tokio::spawn(async move {
                         {
                             let mut tc = thread_count.lock().unwrap();
                             *tc += 1;
                         }
                         for _count in 0..loop_num {
-                            let mut re_vec = ctrl.run().await;
+                            if !user.start_iteration() {
+                                info!("thread-{} is out of data", &t);
+                                break;
+                            }
+                            let mut re_vec = ctrl.run(&mut user.ctx).await;
                             {
                                 let tc = thread_count.lock().unwrap();
                                 for re in &mut re_vec {
-                                    re.grp_threads((*tc).clone() as u32);
-                                    re.all_threads((*tc).clone() as u32);
-                                    re.thread_name(format!("Thread Group 1-{}", &t));
-                                    
+                                    stamp(re, *tc as u32, user.ctx.thread_name());
                                 }
                                 
                             }
 
                             _ = test_record_tx.send(re_vec).await;
-                            
+                            user.end_iteration();
                         }
                         {
                             let mut tc = thread_count.lock().unwrap();
@@ -136,3 +243,151 @@ impl ThreadGroup {
     }
 
 }
+
+/// A virtual user of a running group, with what it renews between iterations.
+struct VirtualUser {
+    ctx: VUContext,
+    pool: ClientPool,
+    data_sets: Vec<CsvDataSet>,
+    cursors: Vec<usize>,
+    cookie_manager: Option<CookieManager>,
+}
+
+impl VirtualUser {
+    /// Set the next rows of the data sets, `false` when one of them stops the thread.
+    fn start_iteration(&mut self) -> bool {
+        csv::next_rows(&self.data_sets, &mut self.cursors, self.ctx.vars_mut())
+    }
+
+    /// Renew the client and the cookies when the group says so.
+    fn end_iteration(&mut self) {
+        self.ctx.next_iteration();
+        if self.pool.renew_per_iteration() {
+            self.ctx.set_client(self.pool.client());
+        }
+        if let Some(manager) = self.cookie_manager.as_ref().filter(|m| m.is_clear_each_iteration()) {
+            self.ctx.insert(manager.jar());
+        }
+    }
+}
+
+/// Set the thread fields of a record and of its sub-results.
+fn stamp(re: &mut RecordData, threads: u32, thread_name: &str) {
+    re.grp_threads(threads);
+    re.all_threads(threads);
+    re.thread_name(thread_name.to_string());
+    for sub_result in re.sub_results_mut() {
+        stamp(sub_result, threads, thread_name);
+    }
+}
+
+#[cfg(test)]
+mod group_tests {
+    use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+    use async_trait::async_trait;
+
+    use crate::{Controller, Output, Sampler, context::VUContext, record::RecordData, config::csv::{CsvDataSetConfig, OnEof}};
+
+    use super::ThreadGroup;
+
+    struct Sleep(&'static str, Duration);
+
+    #[async_trait]
+    impl Sampler for Sleep {
+        fn label(&self) -> &str {
+            self.0
+        }
+
+        async fn run(&self, _ctx: &mut VUContext) -> RecordData {
+            tokio::time::sleep(self.1).await;
+            RecordData::new(0, 0, self.0.to_string(), 200, "OK".to_string(), "".to_string(), "text".to_string(), true, None, 0, 0, 0, 0, "".to_string(), 0, 0, 0, None)
+        }
+    }
+
+    #[derive(Clone)]
+    struct Hang;
+
+    #[async_trait]
+    impl Controller for Hang {
+        async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
+            let quick = ctx.sample(&Sleep("quick", Duration::ZERO)).await;
+            let hung = ctx.sample(&Sleep("hung", Duration::from_secs(3600))).await;
+            vec![quick, hung]
+        }
+    }
+
+    #[derive(Default)]
+    struct Records(Vec<RecordData>);
+
+    impl Output for Records {
+        fn write(&mut self, data: RecordData) {
+            self.0.push(data);
+        }
+    }
+
+    #[derive(Clone)]
+    struct Count;
+
+    #[async_trait]
+    impl Controller for Count {
+        async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
+            let label = ctx.render("${__counter(FALSE)}");
+            vec![RecordData::new(0, 0, label, 200, "OK".to_string(), "".to_string(), "text".to_string(), true, None, 0, 0, 0, 0, "".to_string(), 0, 0, 0, None)]
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_shared_counter() {
+        let group = ThreadGroup::new(2, Duration::ZERO, 2, None);
+        for _ in 0..2 {
+            let out = Arc::new(Mutex::new(Records::default()));
+            group.start(Count, out.clone()).await;
+            let mut labels: Vec<String> = out.lock().unwrap().0.iter().map(|re| re.get_label().to_string()).collect();
+            labels.sort();
+            assert_eq!(labels, ["1", "2", "3", "4"]);
+        }
+    }
+
+    #[derive(Clone)]
+    struct Row;
+
+    #[async_trait]
+    impl Controller for Row {
+        async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
+            let label = ctx.render("${id}");
+            vec![RecordData::new(0, 0, label, 200, "OK".to_string(), "".to_string(), "text".to_string(), true, None, 0, 0, 0, 0, "".to_string(), 0, 0, 0, None)]
+        }
+    }
+
+    #[tokio::test]
+    async fn run_out_of_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ids.csv");
+        std::fs::write(&path, "id\n1\n2\n3\n").unwrap();
+        let data_set = CsvDataSetConfig::new(&path).on_eof(OnEof::StopThread).build().unwrap();
+        let group = ThreadGroup::new(2, Duration::ZERO, -1, Some(Duration::from_secs(60))).csv_data_set(data_set);
+        for _ in 0..2 {
+            let out = Arc::new(Mutex::new(Records::default()));
+            tokio::time::timeout(Duration::from_secs(5), group.start(Row, out.clone())).await.unwrap();
+            let mut labels: Vec<String> = out.lock().unwrap().0.iter().map(|re| re.get_label().to_string()).collect();
+            labels.sort();
+            assert_eq!(labels, ["1", "2", "3"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_during_sample() {
+        let out = Arc::new(Mutex::new(Records::default()));
+        let start = Instant::now();
+        ThreadGroup::new(1, Duration::ZERO, -1, Some(Duration::from_millis(100))).start(Hang, out.clone()).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        let records = &out.lock().unwrap().0;
+        let labels: Vec<(&str, bool)> = records.iter().map(|re| (re.get_label(), re.is_success())).collect();
+        assert_eq!(labels, [("quick", true), ("hung", false)]);
+        assert_eq!(records[1].get_failure_message(), Some("interrupted by the end of the test"));
+        assert_eq!(records[1].get_response_message(), "Interrupted");
+        // the hung sample is recorded with the time it ran until the end
+        assert!(records[1].get_elapsed() > 0 && records[1].get_time_stamp() > 0);
+    }
+}
@@ -1,21 +1,70 @@
 use async_trait::async_trait;
+use context::VUContext;
 use record::RecordData;
 
+pub mod context;
 pub mod group;
 pub mod record;
 pub mod samplers;
 pub mod output;
+pub mod assertions;
+pub mod extractors;
+pub mod variables;
+pub mod functions;
+pub mod config;
 
 #[async_trait]
 pub trait Sampler {
-    async fn run(&self) -> RecordData;
+    async fn run(&self, ctx: &mut VUContext) -> RecordData;
+
+    /// Label of the record of a sample interrupted before it made one, see
+    /// [`VUContext::sample`].
+    fn label(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
 }
 
+/// Runs the samplers of one iteration. The ones run with [`VUContext::sample`]
+/// are still recorded when the group stops in the middle of the iteration.
 #[async_trait]
 pub trait Controller {
-    async fn run(&self) -> Vec<RecordData>;
+    async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData>;
 }
 
 pub trait Output {
     fn write(&mut self, data: RecordData);
-}
\ No newline at end of file
+}
+
+/// Checks the result of a sample, like the assertions of JMeter.
+pub trait Assertion: Send + Sync {
+    /// Name written in front of the reason when the assertion fails.
+    fn name(&self) -> String;
+
+    /// `Err` carries the reason why the sample does not pass.
+    fn assert(&self, data: &RecordData) -> Result<(), String>;
+
+    /// Whether it looks at the response body, which samplers otherwise do not
+    /// keep by default.
+    fn reads_body(&self) -> bool {
+        true
+    }
+
+    /// Error response codes it expects, which then do not fail the sample by
+    /// themselves, like the "ignore status" of JMeter.
+    fn expected_codes(&self) -> &[u16] {
+        &[]
+    }
+}
+
+/// Pulls values out of a sample into the variables of the virtual user, like
+/// the post-processors of JMeter.
+pub trait Extractor: Send + Sync {
+    /// Write what it found with [`VUContext::vars_mut`].
+    fn extract(&self, data: &RecordData, ctx: &mut VUContext);
+
+    /// Whether it looks at the response body, which samplers otherwise do not
+    /// keep by default.
+    fn reads_body(&self) -> bool {
+        true
+    }
+}
@@ -16,7 +16,11 @@ impl FileOutput {
 }
 
 impl Output for FileOutput {
+    /// Sub-results follow their parent on lines of their own, like JMeter does.
     fn write(&mut self, data: RecordData) {
         self.file.write_all(format!("{}\n", data).as_bytes()).unwrap();
+        for sub_result in data.sub_results() {
+            self.write(sub_result.clone());
+        }
     }
 }
\ No newline at end of file
@@ -1,6 +1,11 @@
-use std::{fmt::Display, collections::HashMap};
+use std::{fmt::Display, collections::HashMap, borrow::Cow, sync::Arc, time::Duration};
 
-pub const TITLE_NAMES: [&str; 17] = ["timeStamp", "elapsed", "label", "responseCode", "responseMessage", "threadName", "dataType", "success", "failureMessage", "bytes", "sentBytes", "grpThreads", "allThreads", "URL", "Latency", "IdleTime", "Connect"];
+pub const TITLE_NAMES: [&str; 19] = ["timeStamp", "elapsed", "label", "responseCode", "responseMessage", "threadName", "dataType", "success", "failureMessage", "bytes", "sentBytes", "grpThreads", "allThreads", "URL", "Latency", "IdleTime", "Connect", "bodySize", "decodedBodySize"];
+
+/// Response code of a sample running out of time before its response came. It
+/// is not an http status, so that timeouts are counted apart from http errors.
+/// A body timing out keeps the status of its head.
+pub const TIMEOUT_CODE: u16 = 1000;
 
 #[derive(Clone)]
 pub struct RecordData {
@@ -21,30 +26,193 @@ pub struct RecordData {
     latency: u64,
     idle_time: u64,
     connect: u64,
+    body_size: u64,
+    decoded_body_size: u64,
+    tls: Option<TlsSession>,
+    http_version: Option<String>,
+    graphql: Option<GraphQLOutcome>,
+    subscription: Option<SubscriptionStats>,
     response_result: Option<ResponseResult>,
+    sub_results: Vec<RecordData>,
 }
 
 #[derive(Clone)]
 pub struct ResponseResult {
     response_headers: HashMap<String, String>,
-    response_data: String,
+    response_data: ResponseData,
+    cookies: HashMap<String, String>,
+}
+
+/// The body is shared, so cloning a record does not copy it.
+#[derive(Clone)]
+enum ResponseData {
+    Text(Arc<str>),
+    Bytes(Arc<[u8]>),
 }
 
 impl ResponseResult {
     pub fn new(response_headers: HashMap<String, String>, response_data: String) -> Self {
-        Self { response_headers, response_data }
+        Self { response_headers, response_data: ResponseData::Text(response_data.into()), cookies: HashMap::new() }
+    }
+
+    /// A body kept as bytes, not decoded to text.
+    pub fn from_bytes(response_headers: HashMap<String, String>, response_data: Vec<u8>) -> Self {
+        Self { response_headers, response_data: ResponseData::Bytes(response_data.into()), cookies: HashMap::new() }
+    }
+
+    /// Cookies of the virtual user matching the url once the response is handled.
+    pub fn with_cookies(mut self, cookies: HashMap<String, String>) -> Self {
+        self.cookies = cookies;
+        self
     }
 
     pub fn get_headers(&self) -> HashMap<String, String> {
         self.response_headers.clone()
     }
 
+    /// The body as text, a body kept as bytes is decoded lossily.
     pub fn get_response_data(&self) -> String {
-        self.response_data.clone()
+        match &self.response_data {
+            ResponseData::Text(text) => text.to_string(),
+            ResponseData::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+
+    /// Borrow the headers without cloning them.
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.response_headers
+    }
+
+    /// Borrow the response body without cloning it. A body kept as bytes which
+    /// is not utf-8 gives an empty text, see [`ResponseResult::response_bytes`].
+    pub fn response_data(&self) -> &str {
+        match &self.response_data {
+            ResponseData::Text(text) => text,
+            ResponseData::Bytes(bytes) => std::str::from_utf8(bytes).unwrap_or(""),
+        }
+    }
+
+    /// Borrow the response body as bytes, whatever way it was kept.
+    pub fn response_bytes(&self) -> &[u8] {
+        match &self.response_data {
+            ResponseData::Text(text) => text.as_bytes(),
+            ResponseData::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// Whether the body was kept as bytes.
+    pub fn is_binary(&self) -> bool {
+        matches!(self.response_data, ResponseData::Bytes(_))
+    }
+
+    /// Value of a header, the name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.response_headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn cookies(&self) -> &HashMap<String, String> {
+        &self.cookies
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies.get(name).map(String::as_str)
+    }
+}
+
+/// Protocol and cipher suite a connection negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsSession {
+    pub(crate) protocol: &'static str,
+    pub(crate) cipher: &'static str,
+}
+
+impl TlsSession {
+    /// `TLSv1.2` or `TLSv1.3`.
+    pub fn get_protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    /// IANA name of the cipher suite, like `TLS13_AES_256_GCM_SHA384`.
+    pub fn get_cipher(&self) -> &'static str {
+        self.cipher
+    }
+}
+
+impl Display for TlsSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.protocol, self.cipher)
+    }
+}
+
+/// What a GraphQL response says besides its data.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GraphQLOutcome {
+    pub(crate) errors: usize,
+    pub(crate) data: bool,
+    pub(crate) message: Option<String>,
+    pub(crate) path: Option<String>,
+}
+
+impl GraphQLOutcome {
+    pub fn get_errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Whether the response has non-null `data`.
+    pub fn has_data(&self) -> bool {
+        self.data
+    }
+
+    /// Whether the response has both data and errors.
+    pub fn is_partial(&self) -> bool {
+        self.data && self.errors > 0
+    }
+
+    /// Message of the first error.
+    pub fn get_message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Path of the first error, like `user.friends.0.name`.
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+/// What a GraphQL subscription received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubscriptionStats {
+    pub(crate) ack: Duration,
+    pub(crate) first_event: Option<Duration>,
+    pub(crate) events: usize,
+    pub(crate) event_bytes: u64,
+}
+
+impl SubscriptionStats {
+    /// Time from `connection_init` to `connection_ack`.
+    pub fn get_ack(&self) -> Duration {
+        self.ack
+    }
+
+    /// Time from the subscription to its first event.
+    pub fn get_first_event(&self) -> Option<Duration> {
+        self.first_event
+    }
+
+    pub fn get_events(&self) -> usize {
+        self.events
+    }
+
+    /// Size of the event messages.
+    pub fn get_event_bytes(&self) -> u64 {
+        self.event_bytes
     }
 }
 
 impl RecordData {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         time_stamp: u128,
         elapsed: u64,
@@ -83,10 +251,37 @@ impl RecordData {
             latency,
             idle_time,
             connect,
+            body_size: 0,
+            decoded_body_size: 0,
+            tls: None,
+            http_version: None,
+            graphql: None,
+            subscription: None,
             response_result,
+            sub_results: Vec::new(),
         }
     }
 
+    pub fn time_stamp(&mut self, time_stamp: u128) {
+        self.time_stamp = time_stamp;
+    }
+
+    pub fn label(&mut self, label: String) {
+        self.label = label;
+    }
+
+    pub fn elapsed(&mut self, elapsed: u64) {
+        self.elapsed = elapsed;
+    }
+
+    pub fn latency(&mut self, latency: u64) {
+        self.latency = latency;
+    }
+
+    pub fn connect(&mut self, connect: u64) {
+        self.connect = connect;
+    }
+
     pub fn thread_name(&mut self, thread_name: String) {
         self.thread_name = thread_name;
     }
@@ -99,33 +294,193 @@ impl RecordData {
         self.all_threads = all_threads;
     }
 
+    pub fn bytes(&mut self, bytes: u64) {
+        self.bytes = bytes;
+    }
+
+    pub fn sent_bytes(&mut self, sent_bytes: u64) {
+        self.sent_bytes = sent_bytes;
+    }
+
+    pub fn data_type(&mut self, data_type: String) {
+        self.data_type = data_type;
+    }
+
+    /// Size of the response body as transferred, before any content decoding.
+    pub fn body_size(&mut self, body_size: u64) {
+        self.body_size = body_size;
+    }
+
+    /// Size of the response body after content decoding (gzip, deflate, br).
+    pub fn decoded_body_size(&mut self, decoded_body_size: u64) {
+        self.decoded_body_size = decoded_body_size;
+    }
+
+    /// TLS of the connection of an https sample.
+    pub fn tls(&mut self, tls: Option<TlsSession>) {
+        self.tls = tls;
+    }
+
+    /// Protocol the response came with, like `HTTP/1.1` or `HTTP/2`.
+    pub fn http_version(&mut self, http_version: Option<String>) {
+        self.http_version = http_version;
+    }
+
+    /// Errors of a GraphQL response.
+    pub fn graphql(&mut self, graphql: Option<GraphQLOutcome>) {
+        self.graphql = graphql;
+    }
+
+    /// What a GraphQL subscription received.
+    pub fn subscription(&mut self, subscription: Option<SubscriptionStats>) {
+        self.subscription = subscription;
+    }
+
+    pub fn success(&mut self, success: bool) {
+        self.success = success;
+    }
+
+    pub fn failure_message(&mut self, failure_message: Option<String>) {
+        self.failure_message = failure_message;
+    }
+
+    /// Add a sample that is part of this one, like a followed redirect.
+    pub fn add_sub_result(&mut self, sub_result: RecordData) {
+        self.sub_results.push(sub_result);
+    }
+
+    pub fn get_time_stamp(&self) -> u128 {
+        self.time_stamp
+    }
+
+    pub fn get_label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn get_elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    pub fn get_latency(&self) -> u64 {
+        self.latency
+    }
+
+    pub fn get_connect(&self) -> u64 {
+        self.connect
+    }
+
+    pub fn get_response_code(&self) -> u16 {
+        self.response_code
+    }
+
+    pub fn get_response_message(&self) -> &str {
+        &self.response_message
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    pub fn get_failure_message(&self) -> Option<&str> {
+        self.failure_message.as_deref()
+    }
+
+    /// Bytes received. The http samplers estimate them from the response head
+    /// and body, see [`crate::samplers::exchange::response_head_size`].
+    pub fn get_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Bytes sent, estimated by the http samplers, see [`crate::samplers::exchange::request_size`].
+    pub fn get_sent_bytes(&self) -> u64 {
+        self.sent_bytes
+    }
+
+    pub fn get_body_size(&self) -> u64 {
+        self.body_size
+    }
+
+    pub fn get_decoded_body_size(&self) -> u64 {
+        self.decoded_body_size
+    }
+
+    /// TLS of an https sample, `None` when it failed or when its connection
+    /// was opened in background. It is not written to the .rtl file.
+    pub fn get_tls(&self) -> Option<TlsSession> {
+        self.tls
+    }
+
+    pub fn get_http_version(&self) -> Option<&str> {
+        self.http_version.as_deref()
+    }
+
+    pub fn get_graphql(&self) -> Option<&GraphQLOutcome> {
+        self.graphql.as_ref()
+    }
+
+    pub fn get_subscription(&self) -> Option<SubscriptionStats> {
+        self.subscription
+    }
+
     pub fn get_response_result(&self) -> Option<ResponseResult> {
         self.response_result.clone()
     }
+
+    /// Borrow the response result without cloning it.
+    pub fn response_result(&self) -> Option<&ResponseResult> {
+        self.response_result.as_ref()
+    }
+
+    pub fn sub_results(&self) -> &[RecordData] {
+        &self.sub_results
+    }
+
+    pub(crate) fn response_result_mut(&mut self) -> Option<&mut ResponseResult> {
+        self.response_result.as_mut()
+    }
+
+    pub fn sub_results_mut(&mut self) -> &mut [RecordData] {
+        &mut self.sub_results
+    }
+}
+
+/// Quote a csv field when it contains a separator, a quote or a line break.
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
 }
 
 impl Display for RecordData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}", 
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}", 
             self.time_stamp, 
             self.elapsed,
-            self.label,
+            csv_field(&self.label),
             self.response_code,
-            self.response_message,
-            self.thread_name,
+            csv_field(&self.response_message),
+            csv_field(&self.thread_name),
             self.data_type,
             self.success,
-            self.failure_message.clone().unwrap_or("".to_string()),
+            csv_field(self.failure_message.as_deref().unwrap_or("")),
             self.bytes,
             self.sent_bytes,
             self.grp_threads,
             self.all_threads,
-            self.url,
+            csv_field(&self.url),
             self.latency,
             self.idle_time,
             self.connect,
+            self.body_size,
+            self.decoded_body_size,
         )
     }
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+
+use rand::Rng;
+
+use crate::context::VUContext;
+
+/// Body of a request. Text, form values, part values and file paths may be
+/// `${...}` templates.
+#[derive(Clone, Debug)]
+pub enum Body {
+    /// Text sent in the charset of the http defaults.
+    Text(String),
+    /// Bytes sent as they are.
+    Bytes(Vec<u8>),
+    /// `application/x-www-form-urlencoded` parameters.
+    Form(Vec<(String, String)>),
+    /// `multipart/form-data` parts.
+    Multipart(Multipart),
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Text(text)
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<Multipart> for Body {
+    fn from(multipart: Multipart) -> Self {
+        Body::Multipart(multipart)
+    }
+}
+
+impl Body {
+    /// Form parameters from name and value pairs.
+    pub fn form(params: &[(&str, &str)]) -> Self {
+        Body::Form(params.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect())
+    }
+
+    /// The bytes to send and the `Content-Type` they need. Files are read here.
+    pub(crate) async fn render(&self, ctx: &mut VUContext) -> Result<(Vec<u8>, Option<String>), String> {
+        match self {
+            Body::Text(text) => {
+                let text = ctx.render(text);
+                Ok((ctx.http_defaults().encode_body(text), None))
+            },
+            Body::Bytes(bytes) => Ok((bytes.clone(), None)),
+            Body::Form(params) => {
+                let mut form = url::form_urlencoded::Serializer::new(String::new());
+                for (name, value) in params {
+                    form.append_pair(&ctx.render(name), &ctx.render(value));
+                }
+                Ok((form.finish().into_bytes(), Some("application/x-www-form-urlencoded".to_string())))
+            },
+            Body::Multipart(multipart) => {
+                let boundary = format!("RumeterBoundary{:016x}", ctx.rng().gen::<u64>());
+                let bytes = multipart.encode(&boundary, ctx).await?;
+                Ok((bytes, Some(format!("multipart/form-data; boundary={}", boundary))))
+            },
+        }
+    }
+}
+
+/// Parts of a `multipart/form-data` body.
+#[derive(Clone, Debug, Default)]
+pub struct Multipart {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug)]
+struct Part {
+    name: String,
+    value: PartValue,
+}
+
+#[derive(Clone, Debug)]
+enum PartValue {
+    Text(String),
+    File { path: PathBuf, content_type: String },
+}
+
+impl Multipart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.parts.push(Part { name: name.to_string(), value: PartValue::Text(value.to_string()) });
+        self
+    }
+
+    /// A file read from disk at each run, sent with its file name.
+    pub fn file(mut self, name: &str, path: impl AsRef<Path>, content_type: &str) -> Self {
+        let value = PartValue::File { path: path.as_ref().to_path_buf(), content_type: content_type.to_string() };
+        self.parts.push(Part { name: name.to_string(), value });
+        self
+    }
+
+    async fn encode(&self, boundary: &str, ctx: &mut VUContext) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        for part in &self.parts {
+            let name = quote(&ctx.render(&part.name));
+            out.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+            match &part.value {
+                PartValue::Text(value) => {
+                    out.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes());
+                    out.extend_from_slice(ctx.render(value).as_bytes());
+                },
+                PartValue::File { path, content_type } => {
+                    let path = PathBuf::from(ctx.render(&path.to_string_lossy()));
+                    let content = tokio::fs::read(&path).await
+                        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+                    let file_name = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+                    out.extend_from_slice(format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                        name,
+                        quote(&file_name),
+                        content_type,
+                    ).as_bytes());
+                    out.extend_from_slice(&content);
+                },
+            }
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        Ok(out)
+    }
+}
+
+/// Escape a name in a `Content-Disposition` header.
+fn quote(name: &str) -> String {
+    name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod body_tests {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{context::VUContext, Sampler, samplers::{http::{HttpSampler, Method}, exchange::ResponseMode, test_server::{read_request, spawn_server}}};
+
+    use super::{Body, Multipart};
+
+    /// A server answering with the number of bytes of the request it received.
+    async fn counting_server() -> String {
+        let addr = spawn_server(|mut socket| async move {
+            while let Some(req) = read_request(&mut socket).await {
+                let count = req.size().to_string();
+                let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", count.len(), count);
+                _ = socket.write_all(resp.as_bytes()).await;
+            }
+        }).await;
+        format!("http://{}/upload", addr)
+    }
+
+    #[tokio::test]
+    async fn render_bodies() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        ctx.vars_mut().set("user", "li dao");
+        let (bytes, content_type) = Body::form(&[("user", "${user}"), ("a&b", "1=2")]).render(&mut ctx).await.unwrap();
+        assert_eq!(bytes, b"user=li+dao&a%26b=1%3D2");
+        assert_eq!(content_type.as_deref(), Some("application/x-www-form-urlencoded"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rumeter_body_tests.txt");
+        std::fs::write(&path, "file content").unwrap();
+        let multipart = Multipart::new().text("user", "${user}").file("upload", &path, "text/plain");
+        let (bytes, content_type) = Body::from(multipart).render(&mut ctx).await.unwrap();
+        let boundary = content_type.unwrap().strip_prefix("multipart/form-data; boundary=").unwrap().to_string();
+        let expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"user\"\r\n\r\nli dao\r\n\
+            --{b}\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"rumeter_body_tests.txt\"\r\nContent-Type: text/plain\r\n\r\nfile content\r\n\
+            --{b}--\r\n",
+            b = boundary,
+        );
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+
+        let missing = Multipart::new().file("upload", "/no/such/file", "text/plain");
+        assert!(Body::from(missing).render(&mut ctx).await.unwrap_err().starts_with("cannot read /no/such/file"));
+    }
+
+    #[tokio::test]
+    async fn count_sent_bytes() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = counting_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rumeter_sent_bytes.bin");
+        std::fs::write(&path, vec![7u8; 3000]).unwrap();
+        let bodies = [
+            Body::from("caf\u{e9} ${__threadNum}"),
+            Body::from(vec![0u8, 159, 146, 150]),
+            Body::form(&[("user", "li dao"), ("pass", "1&2")]),
+            Body::from(Multipart::new().text("note", "hello").file("upload", &path, "application/octet-stream")),
+        ];
+        for body in bodies {
+            let re = HttpSampler::new("upload", &url, Method::POST, Default::default(), None).body(body).response_mode(ResponseMode::Full).run(&mut ctx).await;
+            assert_eq!(re.response_result().unwrap().response_data(), re.get_sent_bytes().to_string());
+        }
+    }
+}
@@ -0,0 +1,662 @@
+use std::{cell::Cell, collections::HashMap, error::Error, fmt, future::Future, net::SocketAddr, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll}, time::{Duration, Instant}};
+
+use hyper_util::client::legacy::connect::{Connection, HttpInfo};
+use reqwest::{Client, Method, Request, RequestBuilder, Response, header::{HeaderMap, HeaderValue, ACCEPT, CONNECTION}};
+use tower::{Layer, Service};
+
+use super::{proxy::ProxyConfig, tls::{TlsContext, TlsSession}};
+
+tokio::task_local! {
+    static CONNECT: ConnectState;
+}
+
+/// What the connector of a request shares with [`timed_connect`] and [`send`].
+#[derive(Default)]
+struct ConnectState {
+    timeout: Option<Duration>,
+    started: Cell<Option<Instant>>,
+    time: Cell<Option<Duration>>,
+    tls: Cell<Option<TlsSession>>,
+    /// Local address of the connection opened for the request, and its TLS.
+    opened: Cell<Option<(SocketAddr, Option<TlsSession>)>>,
+}
+
+/// Called by the TLS of a connection opened for [`send`] once it is negotiated.
+pub(super) fn negotiated(session: TlsSession) {
+    _ = CONNECT.try_with(|c| c.tls.set(Some(session)));
+}
+
+/// Which virtual users share one connection pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolScope {
+    /// every virtual user owns its pool, like a browser per user.
+    Thread,
+    /// all virtual users of a ThreadGroup share one pool.
+    Group,
+}
+
+/// Protocol of the requests of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HttpVersion {
+    /// HTTP/1.1 only.
+    #[default]
+    Http1,
+    /// HTTP/2 when the server agrees in the TLS handshake (ALPN), HTTP/1.1
+    /// otherwise and over plain http.
+    Http2,
+    /// HTTP/2 without asking, also over plain http (h2c).
+    Http2PriorKnowledge,
+}
+
+/// Connection options of the clients handed to the http based samplers.
+///
+/// Works like the "Use KeepAlive" checkbox of JMeter, with a few more knobs.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    keep_alive: bool,
+    max_idle_per_host: usize,
+    idle_timeout: Option<Duration>,
+    new_connection_per_iteration: bool,
+    scope: PoolScope,
+    tls: Option<TlsContext>,
+    http_version: HttpVersion,
+    multiplex: bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            max_idle_per_host: usize::MAX,
+            idle_timeout: Some(Duration::from_secs(90)),
+            new_connection_per_iteration: false,
+            scope: PoolScope::Thread,
+            tls: None,
+            http_version: HttpVersion::default(),
+            multiplex: false,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Reuse connections between requests. When disabled, every request sends
+    /// `Connection: close` and no idle connection is kept.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn max_idle_per_host(mut self, max_idle_per_host: usize) -> Self {
+        self.max_idle_per_host = max_idle_per_host;
+        self
+    }
+
+    /// How long an idle connection is kept in the pool, `None` keeps it forever.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Drop all connections of a virtual user at the end of each loop iteration.
+    pub fn new_connection_per_iteration(mut self, new_connection_per_iteration: bool) -> Self {
+        self.new_connection_per_iteration = new_connection_per_iteration;
+        self
+    }
+
+    pub fn scope(mut self, scope: PoolScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// TLS of the https connections, the system certificates by default.
+    pub fn tls(mut self, tls: TlsContext) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Protocol of the samplers not choosing their own, HTTP/1.1 by default.
+    pub fn http_version(mut self, http_version: HttpVersion) -> Self {
+        self.http_version = http_version;
+        self
+    }
+
+    /// Let the virtual users of a [`PoolScope::Group`] pool send their requests
+    /// as concurrent streams of the same HTTP/2 connections. Off by default:
+    /// each virtual user then opens its own HTTP/2 connections, like a browser,
+    /// while HTTP/1.1 connections are still pooled.
+    pub fn multiplex(mut self, multiplex: bool) -> Self {
+        self.multiplex = multiplex;
+        self
+    }
+
+    pub fn build(&self) -> HttpClient {
+        HttpClient {
+            config: self.clone(),
+            default_headers: self.default_headers(),
+            shared: Arc::default(),
+            own: Arc::default(),
+            sessions: Arc::default(),
+        }
+    }
+
+    fn default_headers(&self) -> HeaderMap {
+        // reqwest always adds `Accept: */*`, keep it here so it is counted in the sent bytes
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+        if !self.keep_alive {
+            default_headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        default_headers
+    }
+
+    /// The reqwest client of one protocol and proxy.
+    fn client(&self, version: HttpVersion, proxy: Option<&ProxyConfig>) -> Client {
+        let alpn: &[&[u8]] = match version {
+            HttpVersion::Http1 => &[b"http/1.1"],
+            HttpVersion::Http2 => &[b"h2", b"http/1.1"],
+            HttpVersion::Http2PriorKnowledge => &[b"h2"],
+        };
+        // redirects are followed by the samplers, so that each one is recorded
+        let mut builder = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .pool_idle_timeout(self.idle_timeout)
+            .connector_layer(ConnectTimerLayer)
+            .use_preconfigured_tls(self.tls.clone().unwrap_or_default().client_config(alpn))
+            .default_headers(self.default_headers());
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(proxy.to_reqwest());
+        }
+        builder = match version {
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder,
+            HttpVersion::Http2PriorKnowledge => builder.http2_prior_knowledge(),
+        };
+        if self.keep_alive {
+            builder = builder.pool_max_idle_per_host(self.max_idle_per_host);
+        } else {
+            builder = builder.pool_max_idle_per_host(0);
+        }
+        builder.build().expect("cannot build http client")
+    }
+}
+
+/// The reqwest clients of a virtual user, one per [`HttpVersion`] and proxy
+/// it uses, together with the headers they add to every request.
+#[derive(Clone)]
+pub struct HttpClient {
+    config: ClientConfig,
+    default_headers: HeaderMap,
+    /// Clients shared by the virtual users of a pool.
+    shared: Arc<Mutex<HashMap<Route, Client>>>,
+    /// HTTP/2 clients of this virtual user, when it does not multiplex.
+    own: Arc<Mutex<HashMap<Route, Client>>>,
+    /// TLS negotiated by the open https connections, by local address.
+    sessions: Arc<Mutex<HashMap<SocketAddr, TlsSession>>>,
+}
+
+impl HttpClient {
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.client(self.config.http_version, None).request(method, url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client(self.config.http_version, None).post(url)
+    }
+
+    pub async fn execute(&self, req: Request) -> reqwest::Result<Response> {
+        self.client(self.config.http_version, None).execute(req).await
+    }
+
+    /// Protocol of the requests not asking for another one.
+    pub fn get_http_version(&self) -> HttpVersion {
+        self.config.http_version
+    }
+
+    fn client(&self, version: HttpVersion, proxy: Option<&ProxyConfig>) -> Client {
+        let clients = if version == HttpVersion::Http1 || self.config.multiplex { &self.shared } else { &self.own };
+        clients.lock().unwrap()
+            .entry((version, proxy.cloned()))
+            .or_insert_with(|| self.config.client(version, proxy))
+            .clone()
+    }
+
+    /// A client for another virtual user of the same pool.
+    fn for_other_user(&self) -> HttpClient {
+        HttpClient { own: Arc::default(), ..self.clone() }
+    }
+
+    pub fn default_headers(&self) -> &HeaderMap {
+        &self.default_headers
+    }
+
+    /// TLS of the connections opened outside of reqwest, like websockets.
+    pub(crate) fn tls(&self) -> TlsContext {
+        self.config.tls.clone().unwrap_or_default()
+    }
+}
+
+type Route = (HttpVersion, Option<ProxyConfig>);
+
+/// Hands out clients to virtual users according to a [`ClientConfig`].
+#[derive(Clone)]
+pub struct ClientPool {
+    config: ClientConfig,
+    shared: Option<HttpClient>,
+}
+
+impl ClientPool {
+    pub fn new(config: ClientConfig) -> Self {
+        let shared = match config.scope {
+            PoolScope::Group => Some(config.build()),
+            PoolScope::Thread => None,
+        };
+        Self { config, shared }
+    }
+
+    /// Client for a virtual user starting a new iteration.
+    pub fn client(&self) -> HttpClient {
+        match &self.shared {
+            Some(c) if !self.config.new_connection_per_iteration => c.for_other_user(),
+            _ => self.config.build(),
+        }
+    }
+
+    pub fn renew_per_iteration(&self) -> bool {
+        self.config.new_connection_per_iteration
+    }
+}
+
+/// Await a request future and return how long it spent opening a new
+/// connection (DNS + TCP + TLS). It is zero when a pooled connection was reused.
+pub async fn timed_connect<F: Future>(f: F) -> (F::Output, Duration) {
+    CONNECT.scope(ConnectState::default(), async {
+        let out = f.await;
+        (out, CONNECT.with(|c| c.time.get()).unwrap_or_default())
+    }).await
+}
+
+/// Time limits of a request, unset ones do not apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    connect: Option<Duration>,
+    response: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time to open a new connection, DNS and TLS included.
+    pub fn connect(mut self, connect: Duration) -> Self {
+        self.connect = Some(connect);
+        self
+    }
+
+    /// Time to wait for the response head once connected, and for each piece
+    /// of the body after that.
+    pub fn response(mut self, response: Duration) -> Self {
+        self.response = Some(response);
+        self
+    }
+
+    /// Time of the whole exchange, from sending the request to the end of the body.
+    pub fn total(mut self, total: Duration) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    pub fn get_connect(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    pub fn get_response(&self) -> Option<Duration> {
+        self.response
+    }
+
+    pub fn get_total(&self) -> Option<Duration> {
+        self.total
+    }
+
+    /// These timeouts, completed by `defaults` where unset.
+    pub fn or(self, defaults: Timeouts) -> Self {
+        Self {
+            connect: self.connect.or(defaults.connect),
+            response: self.response.or(defaults.response),
+            total: self.total.or(defaults.total),
+        }
+    }
+}
+
+/// Which of the [`Timeouts`] expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    Connect,
+    Response,
+    Total,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutKind::Connect => write!(f, "Connect timed out"),
+            TimeoutKind::Response => write!(f, "Response timed out"),
+            TimeoutKind::Total => write!(f, "Request timed out"),
+        }
+    }
+}
+
+/// Why [`send`] got no response.
+#[derive(Debug)]
+pub enum SendError {
+    Timeout(TimeoutKind),
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Timeout(kind) => write!(f, "{}", kind),
+            SendError::Request(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        let mut source = e.source();
+        while let Some(cause) = source {
+            if cause.is::<ConnectTimedOut>() {
+                return SendError::Timeout(TimeoutKind::Connect);
+            }
+            source = cause.source();
+        }
+        SendError::Request(e)
+    }
+}
+
+/// Send `req` and wait for the response head within `timeouts`, `deadline` being
+/// the end of the total timeout, with the client of `version` and `proxy`. Also returns the
+/// time spent to connect, see [`timed_connect`], and the TLS of the connection for https.
+/// The TLS of a connection whose handshake finished in background is unknown.
+pub(crate) async fn send(
+    client: &HttpClient,
+    version: HttpVersion,
+    proxy: Option<&ProxyConfig>,
+    req: Request,
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+) -> (Result<Response, SendError>, Duration, Option<TlsSession>) {
+    let state = ConnectState { timeout: timeouts.connect, ..Default::default() };
+    CONNECT.scope(state, async {
+        let start = Instant::now();
+        let sending = client.client(version, proxy).execute(req);
+        tokio::pin!(sending);
+        let out = loop {
+            // the response timeout runs once connected, or from the start on a pooled connection
+            let waiting_since = CONNECT.with(|c| match (c.started.get(), c.time.get()) {
+                (None, _) => Some(start),
+                (Some(started), Some(time)) => Some(started + time),
+                (Some(_), None) => None,
+            });
+            let response_deadline = timeouts.response.map(|t| waiting_since.unwrap_or_else(Instant::now) + t);
+            let wake = response_deadline.into_iter().chain(deadline).min();
+            let expired = async move {
+                match wake {
+                    Some(wake) => tokio::time::sleep_until(wake.into()).await,
+                    None => futures::future::pending().await,
+                }
+            };
+            tokio::select! {
+                resp = &mut sending => break resp.map_err(SendError::from),
+                _ = expired => {
+                    let now = Instant::now();
+                    if deadline.is_some_and(|d| d <= now) {
+                        break Err(SendError::Timeout(TimeoutKind::Total));
+                    }
+                    if waiting_since.is_some() && response_deadline.is_some_and(|d| d <= now) {
+                        break Err(SendError::Timeout(TimeoutKind::Response));
+                    }
+                },
+            }
+        };
+        let mut sessions = client.sessions.lock().unwrap();
+        // a local address is taken again once its connection closed
+        match CONNECT.with(|c| c.opened.take()) {
+            Some((local, Some(tls))) => _ = sessions.insert(local, tls),
+            Some((local, None)) => _ = sessions.remove(&local),
+            None => {},
+        }
+        let tls = out.as_ref().ok()
+            .and_then(|r| r.extensions().get::<HttpInfo>())
+            .and_then(|info| sessions.get(&info.local_addr()).copied());
+        drop(sessions);
+        (out, CONNECT.with(|c| c.time.get()).unwrap_or_default(), tls)
+    }).await
+}
+
+/// Error of a connector running out of its connect timeout.
+#[derive(Debug)]
+struct ConnectTimedOut;
+
+impl fmt::Display for ConnectTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "connect timed out")
+    }
+}
+
+impl Error for ConnectTimedOut {}
+
+/// Connector layer reporting the time spent in the connector to [`timed_connect`],
+/// and the connection it opened with its TLS to [`send`], and applying the
+/// connect timeout given to [`send`].
+#[derive(Clone)]
+struct ConnectTimerLayer;
+
+impl<S> Layer<S> for ConnectTimerLayer {
+    type Service = ConnectTimer<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectTimer { inner }
+    }
+}
+
+#[derive(Clone)]
+struct ConnectTimer<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for ConnectTimer<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+    S::Response: Connection + Send + 'static,
+    S::Error: From<ConnectTimedOut> + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let start = Instant::now();
+        let timeout = CONNECT.try_with(|c| {
+            c.started.set(Some(start));
+            c.timeout
+        }).ok().flatten();
+        let connecting = self.inner.call(req);
+        Box::pin(async move {
+            let conn = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, connecting).await.map_err(|_| ConnectTimedOut)??,
+                None => connecting.await?,
+            };
+            // the connector is polled by the request future, unless the request
+            // got another pooled connection first and this one finished in background
+            let mut extras = http::Extensions::new();
+            conn.connected().get_extras(&mut extras);
+            _ = CONNECT.try_with(|c| {
+                c.time.set(Some(start.elapsed()));
+                if let Some(info) = extras.get::<HttpInfo>() {
+                    c.opened.set(Some((info.local_addr(), c.tls.take())));
+                }
+            });
+            Ok(conn)
+        })
+    }
+}
+
+#[cfg(test)]
+mod client_tests {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+    use crate::{context::VUContext, Sampler, samplers::{http::{HttpSampler, Method}, test_server::{read_request, self_signed, spawn_server, tls_server_config}, tls::TlsConfig}};
+
+    use super::{ClientConfig, ClientPool, HttpClient, HttpVersion, PoolScope};
+
+    /// A server answering `ok` over HTTP/2, or over the protocol chosen by ALPN
+    /// with `tls`. Also returns the number of connections it accepted.
+    async fn h2_server(tls: Option<rustls::ServerConfig>) -> (String, Arc<AtomicUsize>) {
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        let acceptor = tls.map(|tls| tokio_rustls::TlsAcceptor::from(Arc::new(tls)));
+        let addr = spawn_server(move |socket| {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let acceptor = acceptor.clone();
+            async move {
+                match acceptor {
+                    Some(acceptor) => {
+                        let Ok(stream) = acceptor.accept(socket).await else { return };
+                        if stream.get_ref().1.alpn_protocol() == Some(b"h2") {
+                            serve_h2(stream).await;
+                        } else {
+                            serve_h1(stream).await;
+                        }
+                    },
+                    None => serve_h2(socket).await,
+                }
+            }
+        }).await;
+        (format!("{}://localhost:{}/", scheme, addr.port()), connections)
+    }
+
+    /// A server answering `ok` over HTTP/1.1, with the number of connections it accepted.
+    async fn h1_server() -> (String, Arc<AtomicUsize>) {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        let addr = spawn_server(move |socket| {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            serve_h1(socket)
+        }).await;
+        (format!("http://{}/", addr), connections)
+    }
+
+    async fn serve_h2<T: AsyncRead + AsyncWrite + Unpin>(io: T) {
+        let Ok(mut conn) = h2::server::handshake(io).await else { return };
+        while let Some(Ok((_, mut respond))) = conn.accept().await {
+            let resp = http::Response::builder().status(200).header("content-length", "2").body(()).unwrap();
+            let mut body = respond.send_response(resp, false).unwrap();
+            _ = body.send_data(bytes::Bytes::from_static(b"ok"), true);
+        }
+    }
+
+    async fn serve_h1<T: AsyncRead + AsyncWrite + Unpin>(mut io: T) {
+        while read_request(&mut io).await.is_some() {
+            if io.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn get(client: HttpClient, url: &str, version: Option<HttpVersion>) -> crate::record::RecordData {
+        let mut ctx = VUContext::with_client(1, "Thread Group 1-1", client, None, Default::default());
+        let mut sampler = HttpSampler::new("h2", url, Method::GET, Default::default(), None);
+        if let Some(version) = version {
+            sampler = sampler.http_version(version);
+        }
+        sampler.run(&mut ctx).await
+    }
+
+    #[tokio::test]
+    async fn reuse_connections() {
+        // keep-alive, renewal at each iteration, and the connections of the iterations
+        let cases = [
+            (ClientConfig::default(), 1),
+            (ClientConfig::default().keep_alive(false), 3),
+            (ClientConfig::default().new_connection_per_iteration(true), 3),
+        ];
+        for (config, expected) in cases {
+            let (url, connections) = h1_server().await;
+            let pool = ClientPool::new(config);
+            let mut client = pool.client();
+            for _ in 0..3 {
+                assert!(get(client.clone(), &url, None).await.is_success());
+                if pool.renew_per_iteration() {
+                    client = pool.client();
+                }
+            }
+            assert_eq!(connections.load(Ordering::SeqCst), expected);
+        }
+
+        // two virtual users, each with its own pool or sharing the one of the group
+        for (scope, expected) in [(PoolScope::Thread, 2), (PoolScope::Group, 1)] {
+            let (url, connections) = h1_server().await;
+            let pool = ClientPool::new(ClientConfig::default().scope(scope));
+            let users = [pool.client(), pool.client()];
+            for _ in 0..2 {
+                for client in &users {
+                    assert!(get(client.clone(), &url, None).await.is_success());
+                }
+            }
+            assert_eq!(connections.load(Ordering::SeqCst), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn choose_protocol() {
+        let (url, _) = h2_server(None).await;
+        let re = get(ClientConfig::default().http_version(HttpVersion::Http2PriorKnowledge).build(), &url, None).await;
+        assert!(re.is_success());
+        assert_eq!(re.get_http_version(), Some("HTTP/2"));
+        // HEADERS with :status 200 and content-length 2, DATA with the body
+        assert_eq!(re.get_bytes(), 9 + 1 + 1 + 7 + 1 + 3 + 1 + 1 + 14 + 1 + 1 + 9 + 2);
+
+        let server = self_signed("localhost");
+        let dir = tempfile::tempdir().unwrap();
+        let ca = dir.path().join("rumeter_h2_server.pem");
+        std::fs::write(&ca, server.cert.pem()).unwrap();
+        let mut tls = tls_server_config(&server, rustls::DEFAULT_VERSIONS, None);
+        tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let (url, _) = h2_server(Some(tls)).await;
+        let config = ClientConfig::default().tls(TlsConfig::new().ca_bundle(&ca).build().unwrap());
+
+        let re = get(config.clone().build(), &url, None).await;
+        assert_eq!(re.get_http_version(), Some("HTTP/1.1"));
+        let re = get(config.clone().build(), &url, Some(HttpVersion::Http2)).await;
+        assert_eq!(re.get_http_version(), Some("HTTP/2"));
+        let re = get(config.http_version(HttpVersion::Http2).build(), &url, None).await;
+        assert_eq!(re.get_http_version(), Some("HTTP/2"));
+    }
+
+    #[tokio::test]
+    async fn multiplex_virtual_users() {
+        for (multiplex, expected) in [(false, 2), (true, 1)] {
+            let (url, connections) = h2_server(None).await;
+            let config = ClientConfig::default()
+                .scope(PoolScope::Group)
+                .http_version(HttpVersion::Http2PriorKnowledge)
+                .multiplex(multiplex);
+            let pool = ClientPool::new(config);
+            for _ in 0..2 {
+                assert!(get(pool.client(), &url, None).await.is_success());
+            }
+            assert_eq!(connections.load(Ordering::SeqCst), expected);
+        }
+    }
+}
@@ -0,0 +1,877 @@
+use std::{collections::HashMap, io::Read, time::{Duration, Instant}};
+
+use reqwest::{Method, Request, RequestBuilder, Response, Version, header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, LOCATION, PROXY_AUTHORIZATION, TRANSFER_ENCODING, WWW_AUTHENTICATE}};
+use encoding_rs::Encoding;
+use sha2::{Digest, Sha256};
+use tracing::*;
+
+use crate::{record::{RecordData, ResponseResult, TIMEOUT_CODE}, context::VUContext, config::cookie::CookieJar};
+
+use super::{client::{self, HttpVersion, SendError, Timeouts, TimeoutKind}, proxy::ProxyConfig};
+
+/// Which redirects a sampler follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedirectPolicy {
+    /// Follow up to 10 redirects.
+    #[default]
+    Follow,
+    /// Record the redirect response itself.
+    NoFollow,
+    /// Follow up to n redirects, the sample fails past that.
+    Max(usize),
+}
+
+impl RedirectPolicy {
+    fn max(&self) -> usize {
+        match self {
+            RedirectPolicy::Follow => 10,
+            RedirectPolicy::NoFollow => 0,
+            RedirectPolicy::Max(n) => *n,
+        }
+    }
+}
+
+/// What a sampler keeps of the response body. The bytes are counted in
+/// every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResponseMode {
+    /// The text of the body when an assertion or an extractor of the sampler
+    /// reads it, nothing otherwise.
+    #[default]
+    Auto,
+    /// Keep nothing.
+    Discard,
+    /// The hex SHA-256 of the decoded body.
+    Hash,
+    /// The text of the first n bytes of the decoded body.
+    Truncate(usize),
+    /// The decoded body as bytes, see [`ResponseResult::response_bytes`].
+    Raw,
+    /// The text of the body.
+    Full,
+}
+
+/// How a sampler wants its request to be exchanged.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExchangeOptions {
+    pub(crate) timeouts: Timeouts,
+    pub(crate) redirect: RedirectPolicy,
+    /// Keep every response of a followed redirect as a sub-result.
+    pub(crate) redirect_samples: bool,
+    /// Protocol over the one of the client.
+    pub(crate) http_version: Option<HttpVersion>,
+    /// Proxy over the one of the http defaults.
+    pub(crate) proxy: Option<ProxyConfig>,
+    pub(crate) response_mode: ResponseMode,
+    /// Whether an assertion or an extractor reads the body.
+    pub(crate) body_needed: bool,
+    /// Error codes an assertion expects, recorded as a success.
+    pub(crate) expected_codes: Vec<u16>,
+}
+
+/// Send the request built by `builder` with the client and the cookies of the
+/// virtual user, and turn the exchange into a [`RecordData`]. Timeouts are
+/// completed by the ones of the http defaults; a sample running out of time
+/// gets the [`TIMEOUT_CODE`] response code. Redirects are followed here, the
+/// record then adds up all the responses and carries the final url.
+pub(crate) async fn exchange(ctx: &mut VUContext, label: &str, url: &str, builder: RequestBuilder, options: &ExchangeOptions) -> RecordData {
+    let start_send_timestamp = chrono::Local::now();
+    let mut req = match builder.build() {
+        Ok(req) => req,
+        Err(e) => return failed_record(start_send_timestamp, label, url, e.to_string(), 0),
+    };
+    let timeouts = options.timeouts.or(ctx.http_defaults().get_timeouts());
+    let deadline = timeouts.get_total().map(|t| Instant::now() + t);
+    let own_headers = req.headers().clone();
+    let version = options.http_version.unwrap_or(ctx.client().get_http_version());
+    let defaults = ctx.http_defaults();
+    let proxy = options.proxy.as_ref().or(defaults.get_proxy());
+    let mode = match options.response_mode {
+        ResponseMode::Auto if options.body_needed => ResponseMode::Full,
+        ResponseMode::Auto => ResponseMode::Discard,
+        mode => mode,
+    };
+    let mut hops = Vec::new();
+    loop {
+        // a streamed body cannot be sent again, a redirect to a GET does not need it
+        let replay = req.try_clone().ok_or_else(|| Request::new(req.method().clone(), req.url().clone()));
+        let mut re = hop(ctx, label, req, version, proxy, timeouts, deadline, mode, &options.expected_codes).await;
+        let code = re.get_response_code();
+        // a redirect whose body was not read whole ends the chain
+        let location = re.response_result()
+            .filter(|_| re.is_success())
+            .and_then(|r| r.header(LOCATION.as_str()))
+            .map(str::to_string);
+        let next = match (replay, location) {
+            (Ok(prev), Some(location)) => redirect_request(prev, code, &location, &own_headers),
+            (Err(prev), Some(location)) if to_get(code, prev.method()) => redirect_request(prev, code, &location, &own_headers),
+            (Err(_), Some(_)) if is_redirect(code) && options.redirect != RedirectPolicy::NoFollow => {
+                re.success(false);
+                re.failure_message(Some("redirect not followed: body not replayable".to_string()));
+                None
+            },
+            _ => None,
+        };
+        match next {
+            Some(_) if options.redirect == RedirectPolicy::NoFollow => {},
+            Some(next) if hops.len() < options.redirect.max() => {
+                hops.push(re);
+                req = next;
+                continue;
+            },
+            Some(_) => {
+                re.success(false);
+                re.failure_message(Some(format!("more than {} redirects", options.redirect.max())));
+            },
+            None => {},
+        }
+        hops.push(re);
+        break;
+    }
+    merge_hops(label, hops, options.redirect_samples)
+}
+
+/// One record for the responses of a chain, like redirects: the last response, with
+/// the times and sizes of the whole chain.
+pub(crate) fn merge_hops(label: &str, mut hops: Vec<RecordData>, redirect_samples: bool) -> RecordData {
+    let last = hops.pop().expect("an exchange has a response");
+    if hops.is_empty() {
+        return last;
+    }
+    let mut re = last.clone();
+    let before: u64 = hops.iter().map(|h| h.get_elapsed()).sum();
+    re.time_stamp(hops[0].get_time_stamp());
+    re.latency(before + last.get_latency());
+    re.elapsed(before + last.get_elapsed());
+    re.connect(hops.iter().map(|h| h.get_connect()).sum::<u64>() + last.get_connect());
+    re.bytes(hops.iter().map(|h| h.get_bytes()).sum::<u64>() + last.get_bytes());
+    re.sent_bytes(hops.iter().map(|h| h.get_sent_bytes()).sum::<u64>() + last.get_sent_bytes());
+    if redirect_samples {
+        hops.push(last);
+        for (i, mut h) in hops.into_iter().enumerate() {
+            h.label(format!("{}-{}", label, i));
+            re.add_sub_result(h);
+        }
+    }
+    re
+}
+
+fn is_redirect(code: u16) -> bool {
+    matches!(code, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Whether a redirect with `code` turns a request with `method` into a GET
+/// without body: 303, and 301 or 302 after a POST.
+fn to_get(code: u16, method: &Method) -> bool {
+    (code == 303 && method != Method::HEAD) || (matches!(code, 301 | 302) && method == Method::POST)
+}
+
+/// The request following a redirect response, `None` when `code` is not a redirect.
+/// See [`to_get`] for the ones turning it into a GET; the credentials are not
+/// sent to another host.
+fn redirect_request(prev: Request, code: u16, location: &str, own_headers: &HeaderMap) -> Option<Request> {
+    if !is_redirect(code) {
+        return None;
+    }
+    let url = prev.url().join(location).ok()?;
+    let mut headers = own_headers.clone();
+    let to_get = to_get(code, prev.method());
+    let method = if to_get { Method::GET } else { prev.method().clone() };
+    let same_origin = url.host_str() == prev.url().host_str() && url.port_or_known_default() == prev.url().port_or_known_default();
+    if !same_origin {
+        for name in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, WWW_AUTHENTICATE] {
+            headers.remove(name);
+        }
+    }
+    let mut next = Request::new(method, url);
+    if to_get {
+        for name in [CONTENT_TYPE, CONTENT_LENGTH, TRANSFER_ENCODING] {
+            headers.remove(name);
+        }
+    } else {
+        let mut prev = prev;
+        *next.body_mut() = prev.body_mut().take();
+    }
+    *next.headers_mut() = headers;
+    Some(next)
+}
+
+/// What `mode` keeps of a decoded body.
+fn kept_body(mode: ResponseMode, headers: HashMap<String, String>, body: Vec<u8>, content_type: &str, encoding: Option<&'static Encoding>) -> ResponseResult {
+    let text = |body: &[u8]| decode_text(content_type, encoding, body);
+    match mode {
+        ResponseMode::Discard => ResponseResult::new(headers, String::new()),
+        ResponseMode::Hash => ResponseResult::new(headers, format!("{:x}", Sha256::digest(&body))),
+        ResponseMode::Truncate(n) => ResponseResult::new(headers, text(&body[..n.min(body.len())])),
+        ResponseMode::Raw => ResponseResult::from_bytes(headers, body),
+        ResponseMode::Auto | ResponseMode::Full => ResponseResult::new(headers, text(&body)),
+    }
+}
+
+/// Keep what `mode` says of a record exchanged in [`ResponseMode::Raw`], and
+/// of its sub-results, for the samplers looking into the whole body first.
+pub(crate) fn keep_body(re: &mut RecordData, mode: ResponseMode, encoding: Option<&'static Encoding>) {
+    for sub_result in re.sub_results_mut() {
+        keep_body(sub_result, mode, encoding);
+    }
+    if mode == ResponseMode::Raw {
+        return;
+    }
+    if let Some(result) = re.response_result_mut() {
+        let content_type = result.header(CONTENT_TYPE.as_str()).unwrap_or("").to_string();
+        let kept = kept_body(mode, result.get_headers(), result.response_bytes().to_vec(), &content_type, encoding);
+        *result = kept.with_cookies(result.cookies().clone());
+        re.data_type("text".to_string());
+    }
+}
+
+/// Exchange one request and response.
+#[allow(clippy::too_many_arguments)]
+async fn hop(
+    ctx: &mut VUContext,
+    label: &str,
+    mut req: Request,
+    version: HttpVersion,
+    proxy: Option<&ProxyConfig>,
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+    mode: ResponseMode,
+    expected_codes: &[u16],
+) -> RecordData {
+    let start_send_timestamp = chrono::Local::now();
+    let url = &req.url().to_string();
+    if let Some(cookies) = ctx.get::<CookieJar>().and_then(|jar| jar.request_header(req.url())) {
+        add_cookies(req.headers_mut(), cookies);
+    }
+    let client = ctx.client();
+    // the protocol is known once answered, unless it is forced
+    let forwarded = proxy.map(|p| p.forward_size(req.url())).unwrap_or(0);
+    let sent_h1 = request_size(&req, client.default_headers(), Version::HTTP_11) + forwarded;
+    let sent_h2 = request_size(&req, client.default_headers(), Version::HTTP_2);
+    let start = Instant::now();
+    let (resp, connect, tls) = client::send(client, version, proxy, req, timeouts, deadline).await;
+    let latency = start.elapsed();
+    match resp {
+        Ok(r) => {
+            let http_version = r.version();
+            let sent_bytes = if http_version == Version::HTTP_2 { sent_h2 } else { sent_h1 };
+            let data_type = String::from(if mode == ResponseMode::Raw { "bin" } else { "text" });
+            let code = r.status().as_u16();
+            let resp_msg = r.status().canonical_reason().unwrap_or("Unknown");
+            let mut resp_headers: HashMap<String, String> = HashMap::new();
+            for (h_key, h_val) in r.headers() {
+                resp_headers.insert(h_key.to_string(), String::from_utf8_lossy(h_val.as_bytes()).to_string());
+            }
+            let cookies = match ctx.get_mut::<CookieJar>() {
+                Some(jar) => {
+                    jar.store_response(r.headers(), r.url());
+                    jar.matches(r.url())
+                },
+                None => HashMap::new(),
+            };
+            let head_size = response_head_size(&r);
+            let encoding = r.headers().get(CONTENT_ENCODING)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_string();
+            let content_type = r.headers().get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_string();
+
+            // a body without content encoding is only kept as far as needed
+            let identity = matches!(encoding.trim(), "" | "identity");
+            let keep = match mode {
+                ResponseMode::Discard if identity => Some(0),
+                ResponseMode::Truncate(n) if identity => Some(n),
+                _ => None,
+            };
+            let (raw_body, body_size, read) = read_body(r, keep, timeouts.get_response(), deadline).await;
+            let elapsed = start.elapsed();
+            // a body not read whole fails the sample, which keeps the code of its head
+            let fail_msg = match read {
+                Err(failure) => Some(failure),
+                Ok(()) if code < 400u16 || expected_codes.contains(&code) => None,
+                Ok(()) => Some(resp_msg.to_string()),
+            };
+            let success = fail_msg.is_none();
+            let decoded_body = decode_body(&encoding, &raw_body);
+            let decoded_size = if keep.is_some() { body_size } else { decoded_body.len() as u64 };
+            let result = kept_body(mode, resp_headers, decoded_body, &content_type, ctx.http_defaults().get_encoding());
+
+            let mut re = RecordData::new(
+                start_send_timestamp.timestamp_millis() as u128,
+                elapsed.as_millis() as u64,
+                label.to_string(),
+                code,
+                resp_msg.into(),
+                "".to_string(),
+                data_type,
+                success,
+                fail_msg,
+                head_size + body_frames_size(http_version, body_size),
+                sent_bytes,
+                0,
+                0,
+                url.to_string(),
+                latency.as_millis() as u64,
+                0,
+                connect.as_millis() as u64,
+                Some(result.with_cookies(cookies)),
+            );
+            re.body_size(body_size);
+            re.decoded_body_size(decoded_size);
+            re.tls(tls);
+            re.http_version(Some(version_name(http_version).to_string()));
+            re
+        },
+        Err(e) => {
+            error!("failed! --> {}", e.to_string());
+            let sent_bytes = if version == HttpVersion::Http2PriorKnowledge { sent_h2 } else { sent_h1 };
+            let mut re = match e {
+                SendError::Timeout(kind) => timeout_record(start_send_timestamp, label, url, kind, sent_bytes),
+                SendError::Request(e) => failed_record(start_send_timestamp, label, url, e.to_string(), sent_bytes),
+            };
+            re.elapsed(latency.as_millis() as u64);
+            re.latency(latency.as_millis() as u64);
+            re.connect(connect.as_millis() as u64);
+            re.tls(tls);
+            re
+        },
+    }
+}
+
+/// Put the cookies of the jar after the ones the sampler set itself.
+fn add_cookies(headers: &mut HeaderMap, cookies: HeaderValue) {
+    let merged = match headers.get(COOKIE) {
+        Some(own) => {
+            let mut value = own.as_bytes().to_vec();
+            value.extend_from_slice(b"; ");
+            value.extend_from_slice(cookies.as_bytes());
+            HeaderValue::from_bytes(&value).unwrap_or(cookies)
+        },
+        None => cookies,
+    };
+    headers.insert(COOKIE, merged);
+}
+
+/// Read the body, waiting at most `response` for each piece of it and until `deadline`
+/// for all of it, and keeping its first `keep` bytes if given. Returns what was kept,
+/// the size of what was read, and why the body was not read whole. A body timing out
+/// or broken is kept up to there.
+async fn read_body(mut r: Response, keep: Option<usize>, response: Option<Duration>, deadline: Option<Instant>) -> (Vec<u8>, u64, Result<(), String>) {
+    let mut body = Vec::new();
+    let mut size = 0u64;
+    loop {
+        let wake = response.map(|t| Instant::now() + t).into_iter().chain(deadline).min();
+        let chunk = match wake {
+            Some(wake) => match tokio::time::timeout_at(wake.into(), r.chunk()).await {
+                Ok(chunk) => chunk,
+                Err(_) if deadline == Some(wake) => return (body, size, Err(TimeoutKind::Total.to_string())),
+                Err(_) => return (body, size, Err(TimeoutKind::Response.to_string())),
+            },
+            None => r.chunk().await,
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                size += chunk.len() as u64;
+                let room = keep.map_or(chunk.len(), |keep| keep.saturating_sub(body.len()).min(chunk.len()));
+                body.extend_from_slice(&chunk[..room]);
+            },
+            Ok(None) => return (body, size, Ok(())),
+            Err(e) => {
+                warn!("cannot read body: {}", e);
+                return (body, size, Err(format!("cannot read body: {}", e)));
+            },
+        }
+    }
+}
+
+pub(crate) fn failed_record(start_send_timestamp: chrono::DateTime<chrono::Local>, label: &str, url: &str, message: String, sent_bytes: u64) -> RecordData {
+    error_record(start_send_timestamp, label, url, 0, "no data", message, sent_bytes)
+}
+
+pub(crate) fn timeout_record(start_send_timestamp: chrono::DateTime<chrono::Local>, label: &str, url: &str, kind: TimeoutKind, sent_bytes: u64) -> RecordData {
+    error_record(start_send_timestamp, label, url, TIMEOUT_CODE, &kind.to_string(), kind.to_string(), sent_bytes)
+}
+
+pub(crate) fn error_record(
+    start_send_timestamp: chrono::DateTime<chrono::Local>,
+    label: &str,
+    url: &str,
+    code: u16,
+    response_message: &str,
+    message: String,
+    sent_bytes: u64,
+) -> RecordData {
+    RecordData::new(
+        start_send_timestamp.timestamp_millis() as u128,
+        0,
+        label.to_string(),
+        code,
+        response_message.to_string(),
+        "".to_string(),
+        "no data".to_string(),
+        false,
+        Some(message),
+        0u64,
+        sent_bytes,
+        0,
+        0,
+        url.to_string(),
+        0,
+        0,
+        0,
+        None,
+    )
+}
+
+/// Why a sampler did not open its connection, a websocket or a plain TCP one.
+#[derive(Debug)]
+pub(crate) enum ConnectError {
+    Timeout(TimeoutKind),
+    /// The server answered the upgrade with another status.
+    Refused(u16, String),
+    Failed(String),
+}
+
+impl ConnectError {
+    pub(crate) fn record(self, start_send_timestamp: chrono::DateTime<chrono::Local>, label: &str, url: &str) -> RecordData {
+        match self {
+            ConnectError::Timeout(kind) => timeout_record(start_send_timestamp, label, url, kind, 0),
+            ConnectError::Refused(code, message) => error_record(start_send_timestamp, label, url, code, &message, format!("websocket upgrade refused: {}", message), 0),
+            ConnectError::Failed(message) => failed_record(start_send_timestamp, label, url, message, 0),
+        }
+    }
+}
+
+/// Estimate of the bytes of the request as written to the connection: request
+/// line, the headers reqwest and hyper add on their own, and the body. The IO of
+/// the connection is not reachable from reqwest, so nothing is counted there.
+/// TLS records are left out, and for HTTP/2 the headers are counted as HPACK
+/// literals without compression, which is more than what is sent.
+pub fn request_size(req: &Request, default_headers: &HeaderMap, version: Version) -> u64 {
+    let url = req.url();
+    let target = match url.query() {
+        Some(q) => format!("{}?{}", url.path(), q),
+        None => url.path().to_string(),
+    };
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+        None => url.host_str().unwrap_or("").to_string(),
+    };
+    let headers = req.headers();
+    let body_len = req.body().and_then(|b| b.as_bytes()).map(|b| b.len() as u64);
+    let content_length = body_len.filter(|_| !headers.contains_key(CONTENT_LENGTH)).map(|len| len.to_string());
+    let mut fields: Vec<(&str, &[u8])> = headers.iter()
+        .chain(default_headers.iter().filter(|(name, _)| !headers.contains_key(*name)))
+        .map(|(name, value)| (name.as_str(), value.as_bytes()))
+        .collect();
+    if let Some(len) = &content_length {
+        fields.push((CONTENT_LENGTH.as_str(), len.as_bytes()));
+    }
+    if version == Version::HTTP_2 {
+        let pseudo = [
+            (":method", req.method().as_str().as_bytes()),
+            (":scheme", url.scheme().as_bytes()),
+            (":authority", host.as_bytes()),
+            (":path", target.as_bytes()),
+        ];
+        return h2_head_size(pseudo.into_iter().chain(fields)) + body_frames_size(version, body_len.unwrap_or(0));
+    }
+    if !headers.contains_key(HOST) {
+        fields.push((HOST.as_str(), host.as_bytes()));
+    }
+    // METHOD SP target SP HTTP/1.1 CRLF
+    let mut size = (req.method().as_str().len() + target.len() + " HTTP/1.1\r\n".len() + 1) as u64;
+    for (name, value) in fields {
+        size += header_size(name, value);
+    }
+    // empty line closing the head
+    size + 2 + body_len.unwrap_or(0)
+}
+
+/// Estimate of the bytes of the response status line and headers, the same way
+/// as [`request_size`].
+pub fn response_head_size(resp: &Response) -> u64 {
+    let status = resp.status();
+    let fields = resp.headers().iter().map(|(name, value)| (name.as_str(), value.as_bytes()));
+    if resp.version() == Version::HTTP_2 {
+        return h2_head_size([(":status", status.as_str().as_bytes())].into_iter().chain(fields));
+    }
+    // HTTP/1.1 SP code SP reason CRLF
+    let mut size = ("HTTP/1.1 200 \r\n".len() + status.canonical_reason().unwrap_or("").len()) as u64;
+    for (name, value) in fields {
+        size += header_size(name, value);
+    }
+    size + 2
+}
+
+fn header_size(name: &str, value: &[u8]) -> u64 {
+    // name: value CRLF
+    (name.len() + ": \r\n".len() + value.len()) as u64
+}
+
+/// Size of a HEADERS frame, the connection headers HTTP/2 does not send left out.
+fn h2_head_size<'a>(fields: impl Iterator<Item = (&'a str, &'a [u8])>) -> u64 {
+    let block: u64 = fields
+        .filter(|(name, _)| !matches!(*name, "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" | "host"))
+        // literal header field with a new name: one byte, then name and value with their lengths
+        .map(|(name, value)| 1 + hpack_int_size(name.len()) + name.len() as u64 + hpack_int_size(value.len()) + value.len() as u64)
+        .sum();
+    H2_FRAME_HEAD + block
+}
+
+/// Size of an HPACK integer with a 7 bits prefix.
+fn hpack_int_size(n: usize) -> u64 {
+    if n < 127 {
+        return 1;
+    }
+    let mut rest = n - 127;
+    let mut size = 2;
+    while rest >= 128 {
+        rest >>= 7;
+        size += 1;
+    }
+    size
+}
+
+const H2_FRAME_HEAD: u64 = 9;
+const H2_MAX_FRAME: u64 = 16_384;
+
+/// Bytes of a body of `len` bytes: as it is for HTTP/1.1, in DATA frames for HTTP/2.
+fn body_frames_size(version: Version, len: u64) -> u64 {
+    if version == Version::HTTP_2 {
+        len + len.div_ceil(H2_MAX_FRAME) * H2_FRAME_HEAD
+    } else {
+        len
+    }
+}
+
+fn version_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// Decode a body according to its `Content-Encoding`. Unknown encodings and
+/// broken payloads are returned as they are.
+pub fn decode_body(encoding: &str, raw: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let result = match encoding.trim().to_ascii_lowercase().as_str() {
+        "gzip" | "x-gzip" => flate2::read::GzDecoder::new(raw).read_to_end(&mut decoded),
+        "deflate" => flate2::read::ZlibDecoder::new(raw).read_to_end(&mut decoded),
+        "br" => brotli_decompressor::Decompressor::new(raw, 4096).read_to_end(&mut decoded),
+        _ => return raw.to_vec(),
+    };
+    match result {
+        Ok(_) => decoded,
+        Err(e) => {
+            warn!("cannot decode {} body: {}", encoding, e);
+            raw.to_vec()
+        },
+    }
+}
+
+/// Turn a body into text with the charset of its `Content-Type`, or `default`,
+/// or utf-8. Invalid sequences are replaced.
+pub fn decode_text(content_type: &str, default: Option<&'static Encoding>, body: &[u8]) -> String {
+    let charset = content_type.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches('"').as_bytes()));
+    match charset.or(default) {
+        Some(encoding) => encoding.decode(body).0.into_owned(),
+        None => String::from_utf8_lossy(body).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod exchange_tests {
+    use std::io::Write;
+
+    use reqwest::{header::{HeaderMap, HeaderValue, ACCEPT}, Version};
+
+    use std::time::Duration;
+
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{context::VUContext, record::TIMEOUT_CODE, Sampler, samplers::{http::{HttpSampler, Method}, test_server::{read_request, self_signed, spawn_server, tls_server_config}}, assertions::{TextPattern, response::{ResponseCodeAssertion, ResponseTextAssertion}}};
+
+    use super::{decode_body, decode_text, exchange, request_size, ExchangeOptions, RedirectPolicy, ResponseMode};
+
+    /// A server answering every connection with `head`, then the body one byte
+    /// every `pace`, never closing.
+    async fn stalled_server(head: &'static str, body: &'static str, pace: Duration) -> String {
+        let addr = spawn_server(move |mut socket| async move {
+            _ = read_request(&mut socket).await;
+            _ = socket.write_all(head.as_bytes()).await;
+            for b in body.bytes() {
+                tokio::time::sleep(pace).await;
+                _ = socket.write_all(&[b]).await;
+            }
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        }).await;
+        format!("http://{}/", addr)
+    }
+
+    /// An https server taking `delay` to finish the TLS handshake, then again to
+    /// answer with the head, and again to send the body of every request.
+    async fn slow_tls_server(delay: Duration) -> String {
+        let config = tls_server_config(&self_signed("localhost"), rustls::DEFAULT_VERSIONS, None);
+        let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config));
+        let addr = spawn_server(move |socket| {
+            let acceptor = acceptor.clone();
+            async move {
+                tokio::time::sleep(delay).await;
+                let Ok(mut stream) = acceptor.accept(socket).await else { return };
+                while read_request(&mut stream).await.is_some() {
+                    tokio::time::sleep(delay).await;
+                    _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n").await;
+                    _ = stream.flush().await;
+                    tokio::time::sleep(delay).await;
+                    _ = stream.write_all(b"ok").await;
+                    _ = stream.flush().await;
+                }
+            }
+        }).await;
+        format!("https://localhost:{}/", addr.port())
+    }
+
+    #[tokio::test]
+    async fn split_times() {
+        let tls = crate::samplers::tls::TlsConfig::new().native_roots(false).accept_invalid_certs(true).build().unwrap();
+        let client = crate::samplers::client::ClientConfig::default().tls(tls).build();
+        let mut ctx = VUContext::with_client(1, "Thread Group 1-1", client, None, Default::default());
+        let url = slow_tls_server(Duration::from_millis(100)).await;
+        let get = HttpSampler::new("get", &url, Method::GET, Default::default(), None);
+
+        // connect is the TCP connection and the TLS handshake, latency goes on
+        // to the response head, elapsed to the end of the body; each waits for
+        // one more delay of the server
+        let re = get.run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        let times = (re.get_connect(), re.get_latency(), re.get_elapsed());
+        assert!(times.0 <= times.1 && times.1 <= times.2, "{:?}", times);
+        assert!(times.0 >= 100 && times.1 >= 200 && times.2 >= 300, "{:?}", times);
+
+        let re = get.run(&mut ctx).await;
+        let times = (re.get_connect(), re.get_latency(), re.get_elapsed());
+        assert!(times.0 == 0 && times.1 <= times.2, "{:?}", times);
+        assert!(times.1 >= 100 && times.2 >= 200, "{:?}", times);
+    }
+
+    #[test]
+    fn count_request_bytes() {
+        let mut defaults = HeaderMap::new();
+        defaults.insert(ACCEPT, HeaderValue::from_static("*/*"));
+        let req = reqwest::Client::new()
+            .post("http://example.com:8080/login?a=1")
+            .header("x-token", "abc")
+            .body("hello")
+            .build()
+            .unwrap();
+        let expected = "POST /login?a=1 HTTP/1.1\r\n\
+            x-token: abc\r\n\
+            accept: */*\r\n\
+            host: example.com:8080\r\n\
+            content-length: 5\r\n\
+            \r\n\
+            hello";
+        assert_eq!(request_size(&req, &defaults, Version::HTTP_11), expected.len() as u64);
+        // HEADERS: frame head and the literals of :method, :scheme, :authority, :path,
+        // x-token, accept and content-length; DATA: frame head and body
+        assert_eq!(request_size(&req, &defaults, Version::HTTP_2), 9 + 14 + 14 + 29 + 18 + 13 + 12 + 18 + 9 + 5);
+    }
+
+    #[test]
+    fn decode_gzip_body() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello hello hello").unwrap();
+        let raw = encoder.finish().unwrap();
+        assert_eq!(decode_body("gzip", &raw), b"hello hello hello");
+        assert_eq!(decode_body("identity", b"abc"), b"abc");
+        assert_eq!(decode_body("gzip", b"not gzip"), b"not gzip");
+    }
+
+    #[test]
+    fn decode_charset() {
+        let latin1 = encoding_rs::Encoding::for_label(b"latin1");
+        assert_eq!(decode_text("text/html; charset=\"ISO-8859-1\"", None, b"caf\xe9"), "café");
+        assert_eq!(decode_text("text/plain", latin1, b"caf\xe9"), "café");
+        assert_eq!(decode_text("application/json; charset=utf-8", latin1, "café".as_bytes()), "café");
+        assert_eq!(decode_text("", None, "café".as_bytes()), "café");
+    }
+
+    #[tokio::test]
+    async fn record_timeouts() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let silent = stalled_server("", "", Duration::ZERO).await;
+        let re = HttpSampler::new("silent", &silent, Method::GET, Default::default(), None)
+            .response_timeout(Duration::from_millis(200))
+            .run(&mut ctx).await;
+        assert_eq!((re.get_response_code(), re.get_response_message()), (TIMEOUT_CODE, "Response timed out"));
+        assert!(!re.is_success());
+        assert!(re.get_elapsed() >= 200);
+
+        let head = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n";
+        let slow = stalled_server(head, "0123456789", Duration::from_millis(100)).await;
+        let re = HttpSampler::new("slow body", &slow, Method::GET, Default::default(), None)
+            .response_timeout(Duration::from_millis(300))
+            .timeout(Duration::from_millis(500))
+            .run(&mut ctx).await;
+        // the head came, so the code is kept
+        assert_eq!((re.get_response_code(), re.get_response_message()), (200, "OK"));
+        assert_eq!((re.is_success(), re.get_failure_message()), (false, Some("Request timed out")));
+        assert!(re.get_body_size() < 10 && re.get_bytes() > re.get_body_size());
+
+        // closed after half of the body
+        let broken = spawn_server(|mut socket| async move {
+            _ = read_request(&mut socket).await;
+            _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n01234").await;
+        }).await;
+        let re = HttpSampler::new("broken body", &format!("http://{}/", broken), Method::GET, Default::default(), None).run(&mut ctx).await;
+        assert_eq!((re.get_response_code(), re.is_success()), (200, false));
+        assert!(re.get_failure_message().unwrap().starts_with("cannot read body"));
+
+        let re = HttpSampler::new("slow body", &slow, Method::GET, Default::default(), None)
+            .response_timeout(Duration::from_millis(300))
+            .run(&mut ctx).await;
+        assert_eq!((re.get_response_code(), re.get_decoded_body_size()), (200, 10));
+    }
+
+    /// `/a` redirects to `/b` with a 302, `/b` to `/c` with a 307, `/s` to `/c`
+    /// with a 303, `/c` answers with the method it got.
+    async fn redirect_server() -> String {
+        let addr = spawn_server(|mut socket| async move {
+            while let Some(req) = read_request(&mut socket).await {
+                let resp = match req.target() {
+                    "/a" => "HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n".to_string(),
+                    "/b" => "HTTP/1.1 307 Temporary Redirect\r\nLocation: c\r\nContent-Length: 0\r\n\r\n".to_string(),
+                    "/s" => "HTTP/1.1 303 See Other\r\nLocation: /c\r\nContent-Length: 0\r\n\r\n".to_string(),
+                    _ => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", req.method().len(), req.method()),
+                };
+                _ = socket.write_all(resp.as_bytes()).await;
+            }
+        }).await;
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn follow_redirects() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let base = redirect_server().await;
+        let post = |policy| HttpSampler::new("login", &format!("{}/a", base), Method::POST, Default::default(), Some("x=1".to_string()))
+            .redirect(policy)
+            .redirect_samples(true)
+            .response_mode(ResponseMode::Full);
+
+        let re = post(RedirectPolicy::Follow).run(&mut ctx).await;
+        assert_eq!(re.get_response_code(), 200);
+        assert_eq!(re.get_url(), format!("{}/c", base));
+        assert_eq!(re.response_result().unwrap().response_data(), "GET");
+        let subs: Vec<(&str, u16)> = re.sub_results().iter().map(|s| (s.get_label(), s.get_response_code())).collect();
+        assert_eq!(subs, [("login-0", 302), ("login-1", 307), ("login-2", 200)]);
+        assert_eq!(re.get_bytes(), re.sub_results().iter().map(|s| s.get_bytes()).sum::<u64>());
+
+        let re = post(RedirectPolicy::NoFollow).run(&mut ctx).await;
+        assert_eq!((re.get_response_code(), re.is_success(), re.sub_results().len()), (302, true, 0));
+
+        let re = post(RedirectPolicy::Max(1)).run(&mut ctx).await;
+        assert_eq!((re.get_response_code(), re.get_failure_message()), (307, Some("more than 1 redirects")));
+    }
+
+    #[tokio::test]
+    async fn streamed_body_redirect() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let base = redirect_server().await;
+        // a wrapped body is streamed, it cannot be sent twice
+        let builder = ctx.client().post(&format!("{}/b", base)).body(reqwest::Body::wrap("x=1".to_string()));
+        let re = exchange(&mut ctx, "upload", &base, builder, &Default::default()).await;
+        assert_eq!(re.get_response_code(), 307);
+        assert_eq!((re.is_success(), re.get_failure_message()), (false, Some("redirect not followed: body not replayable")));
+
+        let builder = ctx.client().post(&format!("{}/b", base)).body(reqwest::Body::wrap("x=1".to_string()));
+        let options = ExchangeOptions { redirect: RedirectPolicy::NoFollow, ..Default::default() };
+        assert!(exchange(&mut ctx, "upload", &base, builder, &options).await.is_success());
+
+        // redirects to a GET do not send the body again
+        for path in ["/s", "/a"] {
+            let builder = ctx.client().post(&format!("{}{}", base, path)).body(reqwest::Body::wrap("x=1".to_string()));
+            let options = ExchangeOptions { response_mode: ResponseMode::Full, ..Default::default() };
+            let re = exchange(&mut ctx, "upload", &base, builder, &options).await;
+            assert!(re.is_success(), "{:?}", re.get_failure_message());
+            assert_eq!((re.get_url(), re.response_result().unwrap().response_data()), (format!("{}/c", base).as_str(), "GET"));
+        }
+    }
+
+    /// A server answering every request with `head`, then `body` after its length.
+    async fn body_server(head: &'static str, body: Vec<u8>) -> String {
+        let mut resp = format!("HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\n\r\n", head, body.len()).into_bytes();
+        resp.extend_from_slice(&body);
+        let resp = std::sync::Arc::new(resp);
+        let addr = spawn_server(move |mut socket| {
+            let resp = resp.clone();
+            async move {
+                while read_request(&mut socket).await.is_some() {
+                    _ = socket.write_all(&resp).await;
+                }
+            }
+        }).await;
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn keep_response_body() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = body_server("Content-Type: text/plain\r\n", b"hello world".to_vec()).await;
+        let get = || HttpSampler::new("get", &url, Method::GET, Default::default(), None);
+        let data = |re: crate::record::RecordData| re.response_result().unwrap().response_data().to_string();
+
+        let re = get().assertion(ResponseCodeAssertion::new(&[200])).run(&mut ctx).await;
+        assert_eq!((re.get_body_size(), re.get_decoded_body_size()), (11, 11));
+        assert!(re.get_bytes() > 11);
+        assert_eq!(data(re), "");
+        let re = get().assertion(ResponseTextAssertion::new(TextPattern::Substring("world".to_string()))).run(&mut ctx).await;
+        assert!(re.is_success());
+        assert_eq!(data(re), "hello world");
+        assert_eq!(data(get().response_mode(ResponseMode::Full).run(&mut ctx).await), "hello world");
+        let re = get().response_mode(ResponseMode::Truncate(5)).run(&mut ctx).await;
+        assert_eq!(re.get_body_size(), 11);
+        assert_eq!(data(re), "hello");
+        let re = get().response_mode(ResponseMode::Hash).run(&mut ctx).await;
+        assert_eq!(data(re), "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+
+        let binary = vec![0u8, 159, 146, 150];
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&binary).unwrap();
+        let gzip = encoder.finish().unwrap();
+        let url = body_server("Content-Encoding: gzip\r\n", gzip.clone()).await;
+        let get = || HttpSampler::new("get", &url, Method::GET, Default::default(), None);
+        let re = get().response_mode(ResponseMode::Raw).run(&mut ctx).await;
+        let result = re.response_result().unwrap();
+        assert!(result.is_binary());
+        assert_eq!((result.response_bytes(), result.response_data()), (&binary[..], ""));
+        assert_eq!((re.get_body_size(), re.get_decoded_body_size()), (gzip.len() as u64, 4));
+        let re = get().response_mode(ResponseMode::Truncate(2)).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_data(), "\0\u{fffd}");
+        assert_eq!(re.get_decoded_body_size(), 4);
+    }
+
+    #[tokio::test]
+    async fn expected_error_code() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = stalled_server("HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 4\r\n\r\n", "gone", Duration::ZERO).await;
+        let get = || HttpSampler::new("get", &url, Method::GET, Default::default(), None);
+        let re = get().run(&mut ctx).await;
+        assert_eq!((re.is_success(), re.get_failure_message()), (false, Some("Not Found")));
+        let re = get().assertion(ResponseCodeAssertion::new(&[404])).run(&mut ctx).await;
+        assert_eq!((re.get_response_code(), re.is_success(), re.get_failure_message()), (404, true, None));
+        let re = get().assertion(ResponseCodeAssertion::new(&[200])).run(&mut ctx).await;
+        assert_eq!(re.get_failure_message(), Some("Not Found; Response Code Assertion: response code 404 is not in [200]"));
+    }
+}
@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use serde::Serialize;
-use tracing::*;
-use crate::{Sampler, record::{RecordData, ResponseResult}};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use crate::{Sampler, Assertion, Extractor, record::RecordData, assertions::{self, AssertionRef}, extractors::{self, ExtractorRef}, context::VUContext};
 
-use super::http::HeaderMap;
+pub use crate::record::GraphQLOutcome;
+
+use super::{http::{HeaderMap, render_headers}, gql_document::GraphQLOperation, exchange::{self, ExchangeOptions, ResponseMode}, client::HttpVersion, proxy::ProxyConfig};
 
 
 #[derive(Clone)]
@@ -13,123 +16,527 @@ pub struct GraphQLSampler<T: Serialize + Clone + Send + Sync>{
     label: String,
     endpoint: String,
     headers: HeaderMap,
-    body: RequestBody<T>,
+    operations: Vec<RequestBody<T>>,
+    assertions: Vec<AssertionRef>,
+    extractors: Vec<ExtractorRef>,
+    options: ExchangeOptions,
+    error_policy: ErrorPolicy,
+    transport: Transport,
+    persisted_queries: bool,
+}
+
+/// How a GraphQL request is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// A json body.
+    #[default]
+    Post,
+    /// The url-encoded parameters `query`, `operationName`, `variables` and
+    /// `extensions`, the last two as json. Batches are always posted.
+    Get,
+}
+
+/// When the `errors` of a GraphQL response fail the sample. They are
+/// recorded whatever the policy, see [`GraphQLOutcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Fail on any error, and on a response without `data`.
+    #[default]
+    Strict,
+    /// Fail on a response without `data` only, partial data passes.
+    AllowPartial,
+    /// Never fail because of the response content.
+    Ignore,
+}
+
+impl GraphQLOutcome {
+    /// Read the `data` and `errors` of a response body, or of all the
+    /// responses of a batch.
+    pub fn parse(body: &[u8]) -> Result<Self, serde_json::Error> {
+        let json: Value = serde_json::from_slice(body)?;
+        let responses = match &json {
+            Value::Array(responses) => responses.as_slice(),
+            response => std::slice::from_ref(response),
+        };
+        let errors: Vec<&Value> = responses.iter().flat_map(response_errors).collect();
+        let first = errors.first().copied();
+        let message = first.map(|e| match e.get("message") {
+            Some(Value::String(m)) => m.clone(),
+            Some(m) => m.to_string(),
+            None => e.to_string(),
+        });
+        let path = first.and_then(|e| e.get("path")).and_then(Value::as_array).map(|path| {
+            path.iter()
+                .map(|p| p.as_str().map(str::to_string).unwrap_or_else(|| p.to_string()))
+                .collect::<Vec<_>>()
+                .join(".")
+        });
+        let data = !responses.is_empty() && responses.iter().all(|r| r.get("data").is_some_and(|d| !d.is_null()));
+        Ok(Self { errors: errors.len(), data, message, path })
+    }
+
+    /// Why a sample fails under `policy`, if it does.
+    fn failure(&self, policy: ErrorPolicy) -> Option<String> {
+        let fails = match policy {
+            ErrorPolicy::Strict => self.errors > 0 || !self.data,
+            ErrorPolicy::AllowPartial => !self.data,
+            ErrorPolicy::Ignore => false,
+        };
+        if !fails {
+            return None;
+        }
+        Some(match (&self.message, &self.path) {
+            (Some(message), Some(path)) => format!("{} at {}", message, path),
+            (Some(message), None) => message.clone(),
+            (None, _) => "no data in GraphQL response".to_string(),
+        })
+    }
+}
+
+fn response_errors(response: &Value) -> &[Value] {
+    response.get("errors").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Whether a response asks for the query of a persisted query hash.
+fn persisted_query_not_found(body: &[u8]) -> bool {
+    let json: Value = match serde_json::from_slice(body) {
+        Ok(json) => json,
+        Err(_) => return false,
+    };
+    let responses = match &json {
+        Value::Array(responses) => responses.as_slice(),
+        response => std::slice::from_ref(response),
+    };
+    responses.iter().flat_map(response_errors).any(|e| {
+        e["message"] == "PersistedQueryNotFound" || e["extensions"]["code"] == "PERSISTED_QUERY_NOT_FOUND"
+    })
 }
 
 #[derive(Serialize, Clone)]
-struct RequestBody<T: Serialize + Clone + Send> {
-    query: String,
-    variables: Option<T>,
+pub(super) struct RequestBody<T: Serialize + Clone + Send> {
+    pub(super) query: String,
+    #[serde(rename = "operationName", skip_serializing_if = "Option::is_none")]
+    pub(super) operation_name: Option<String>,
+    pub(super) variables: Option<T>,
 }
 
 impl <T: Serialize + Clone + Send + Sync> GraphQLSampler<T> {
     pub fn new(label: &str, endpoint: &str, query: &str, headers: HeaderMap, vars: Option<T>) -> Self {
         let body = RequestBody {
             query: query.to_string(),
+            operation_name: None,
             variables: vars,
         };
-        Self { label: label.to_string(), endpoint: endpoint.to_string(), headers, body }
+        // the body is always read for its errors
+        let options = ExchangeOptions { body_needed: true, ..Default::default() };
+        Self {
+            label: label.to_string(),
+            endpoint: endpoint.to_string(),
+            headers,
+            operations: vec![body],
+            assertions: Vec::new(),
+            extractors: Vec::new(),
+            options,
+            error_policy: ErrorPolicy::default(),
+            transport: Transport::default(),
+            persisted_queries: false,
+        }
+    }
+
+    /// Name of the operation to run in the query, for the last operation added.
+    pub fn operation_name(mut self, operation_name: &str) -> Self {
+        if let Some(op) = self.operations.last_mut() {
+            op.operation_name = Some(operation_name.to_string());
+        }
+        self
     }
 
-    fn request_size(&self) -> u32 {
-        self.request_line_size() + self.request_headers_size() + self.request_body_size()
+    /// Add an operation sent in the same request, the body becoming an
+    /// array of operations and the response an array of results.
+    pub fn batch(mut self, query: &str, vars: Option<T>) -> Self {
+        self.operations.push(RequestBody { query: query.to_string(), operation_name: None, variables: vars });
+        self
     }
 
-    fn request_headers_size(&self) -> u32 {
-        let mut size = 0u32;
-        for (key, value) in self.headers.clone() {
-            match key {
-                Some(header_name) => {
-                    size = size + (header_name.to_string().len() + value.len() + ":\r\n".len()) as u32;
-                },
-                None => {},
-            }
-        }
-        size
+    /// POST by default.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
     }
 
-    fn request_line_size(&self) -> u32 {
-        ("POST".len() + self.endpoint.len() + "  HTTP/1.1\r\n".len()) as u32
+    /// Send the SHA-256 hash of the query instead of the query, like the
+    /// Automatic Persisted Queries of Apollo. A server not knowing the hash yet
+    /// answers `PersistedQueryNotFound`, the request is then sent again with
+    /// the query and both responses are kept as sub-results.
+    pub fn persisted_queries(mut self, persisted_queries: bool) -> Self {
+        self.persisted_queries = persisted_queries;
+        self
     }
 
-    fn request_body_size(&self) -> u32 {
-        let s = serde_json::to_string(&self.body);
-        (s.unwrap_or("".to_string()).len() + "\r\n".len()) as u32 
+    /// When the errors of the response fail the sample, on any of them by default.
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    pub fn assertion(mut self, assertion: impl Assertion + 'static) -> Self {
+        self.options.body_needed |= assertion.reads_body();
+        self.options.expected_codes.extend_from_slice(assertion.expected_codes());
+        self.assertions.push(Arc::new(assertion));
+        self
+    }
+
+    pub fn extractor(mut self, extractor: impl Extractor + 'static) -> Self {
+        self.options.body_needed |= extractor.reads_body();
+        self.extractors.push(Arc::new(extractor));
+        self
+    }
+
+    /// Time to open a new connection, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeouts = self.options.timeouts.connect(timeout);
+        self
+    }
+
+    /// Time to wait for the response head and each piece of the body.
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeouts = self.options.timeouts.response(timeout);
+        self
+    }
+
+    /// Time of the whole exchange.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeouts = self.options.timeouts.total(timeout);
+        self
+    }
+
+    /// Protocol of this sampler, over the one of the [`super::client::ClientConfig`].
+    pub fn http_version(mut self, http_version: HttpVersion) -> Self {
+        self.options.http_version = Some(http_version);
+        self
+    }
+
+    /// Proxy of this sampler, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.options.proxy = Some(proxy);
+        self
+    }
+
+    /// What to keep of the response body, the whole text by default. Errors
+    /// and persisted query misses are looked for in the whole body first.
+    pub fn response_mode(mut self, response_mode: ResponseMode) -> Self {
+        self.options.response_mode = response_mode;
+        self
+    }
+
+    /// Send one operation alone, more of them as an array.
+    async fn send(&self, ctx: &mut VUContext, endpoint: &str, headers: HeaderMap, operations: &[Value], options: &ExchangeOptions) -> RecordData {
+        let req = match operations {
+            [op] if self.transport == Transport::Get => {
+                let params: Vec<(&str, String)> = op.as_object().into_iter().flatten()
+                    .filter(|(_, v)| !v.is_null())
+                    .map(|(k, v)| (k.as_str(), v.as_str().map(str::to_string).unwrap_or_else(|| v.to_string())))
+                    .collect();
+                ctx.client().request(reqwest::Method::GET, endpoint).query(&params)
+            },
+            [op] => ctx.client().post(endpoint).json(op),
+            ops => ctx.client().post(endpoint).json(ops),
+        };
+        exchange::exchange(ctx, &self.label, endpoint, req.headers(headers), options).await
+    }
+}
+
+/// A GraphQL sampler with json variables, for plans not known when compiling
+/// like the ones of the command line. Strings in the variables are templates.
+pub type DynamicGraphQLSampler = GraphQLSampler<Value>;
+
+impl GraphQLSampler<Value> {
+    /// A sampler running an operation of a [`super::gql_document::GraphQLDocument`].
+    pub fn from_operation(label: &str, endpoint: &str, operation: &GraphQLOperation, headers: HeaderMap, vars: Option<Value>) -> Self {
+        let sampler = Self::new(label, endpoint, operation.get_query(), headers, vars);
+        match operation.get_name() {
+            Some(name) => sampler.operation_name(name),
+            None => sampler,
+        }
     }
 }
 
 #[async_trait]
 impl <T: Serialize + Clone + Send + Sync> Sampler for GraphQLSampler<T> {
-    async fn run(&self) -> RecordData {
-        let client = reqwest::Client::new();
-        let start_send_timestamp = chrono::Local::now();
-        let resp = client.post(&self.endpoint).json(&self.body).headers(self.headers.clone()).send().await;
-        let finish_send_timestamp = chrono::Local::now();
-
-        match resp {
-            Ok(r) => {
-                let data_type = String::from("text");
-                let code = r.status().as_u16();
-                let resp_msg = r.status().canonical_reason().unwrap_or("Unknown");
-                let success = code < 400u16;
-                let fail_msg = if success {
-                    None
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let defaults = ctx.http_defaults();
+        let endpoint = ctx.render(&self.endpoint);
+        let endpoint = defaults.url(&endpoint, ctx);
+        let mut operations: Vec<Value> = self.operations.iter()
+            .map(|op| serde_json::to_value(op).unwrap_or_default())
+            .collect();
+        for op in operations.iter_mut() {
+            ctx.render_json(op);
+            if self.persisted_queries {
+                let hash = format!("{:x}", Sha256::digest(op["query"].as_str().unwrap_or("")));
+                op["extensions"] = json!({"persistedQuery": {"version": 1, "sha256Hash": hash}});
+            }
+        }
+        let headers = render_headers(&self.headers, ctx);
+        let options = ExchangeOptions { response_mode: ResponseMode::Raw, ..self.options.clone() };
+        let mut re = if self.persisted_queries {
+            let hashed: Vec<Value> = operations.iter()
+                .map(|op| {
+                    let mut op = op.clone();
+                    if let Some(op) = op.as_object_mut() {
+                        op.remove("query");
+                    }
+                    op
+                })
+                .collect();
+            let first = self.send(ctx, &endpoint, headers.clone(), &hashed, &options).await;
+            match first.response_result() {
+                Some(r) if persisted_query_not_found(r.response_bytes()) => {
+                    let second = self.send(ctx, &endpoint, headers, &operations, &options).await;
+                    exchange::merge_hops(&self.label, vec![first, second], true)
+                },
+                _ => first,
+            }
+        } else {
+            self.send(ctx, &endpoint, headers, &operations, &options).await
+        };
+        check_errors(&mut re, self.error_policy);
+        let mode = match self.options.response_mode {
+            ResponseMode::Auto => ResponseMode::Full,
+            mode => mode,
+        };
+        exchange::keep_body(&mut re, mode, defaults.get_encoding());
+        extractors::apply(&self.extractors, &re, ctx);
+        assertions::apply(&self.assertions, &mut re);
+        re
+    }
+}
+
+/// Record the errors of the response, and fail the sample on them as `policy`
+/// says. A sample failed by its http status keeps its message.
+fn check_errors(re: &mut RecordData, policy: ErrorPolicy) {
+    let body = match re.response_result() {
+        Some(result) => result.response_bytes(),
+        None => return,
+    };
+    let failure = match GraphQLOutcome::parse(body) {
+        Ok(outcome) => {
+            let failure = outcome.failure(policy);
+            re.graphql(Some(outcome));
+            failure
+        },
+        Err(e) if policy != ErrorPolicy::Ignore => Some(format!("invalid GraphQL response: {}", e)),
+        Err(_) => None,
+    };
+    if let Some(failure) = failure {
+        if re.is_success() {
+            re.success(false);
+            re.failure_message(Some(failure));
+        }
+    }
+}
+
+#[cfg(test)]
+mod gql_tests {
+    use std::{collections::HashSet, sync::{Arc, Mutex}};
+
+    use reqwest::Url;
+    use serde_json::{json, Value};
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{context::VUContext, Sampler, samplers::test_server::{read_request, spawn_server}};
+
+    use super::{DynamicGraphQLSampler, ErrorPolicy, GraphQLOutcome, GraphQLSampler, ResponseMode, Transport};
+    use crate::samplers::gql_document::GraphQLDocument;
+
+    /// A server answering the queries `ok`, `partial`, `failed` and `broken` as
+    /// their names say, always with a 200.
+    async fn graphql_server() -> String {
+        let addr = spawn_server(|mut socket| async move {
+            while let Some(req) = read_request(&mut socket).await {
+                let req = req.body_text();
+                let body = if req.contains("\"query\":\"ok\"") {
+                    r#"{"data":{"user":{"name":"li dao"}}}"#
+                } else if req.contains("\"query\":\"partial\"") {
+                    r#"{"data":{"user":null},"errors":[{"message":"no user","path":["user",0,"name"]},{"message":"again"}]}"#
+                } else if req.contains("\"query\":\"failed\"") {
+                    r#"{"errors":[{"message":"syntax error"}]}"#
                 } else {
-                    Some(resp_msg.to_string())
+                    "<html>oops</html>"
                 };
-                let mut resp_headers: HashMap<String, String> = HashMap::new();
-                for (h_key, h_val) in r.headers() {
-                    resp_headers.insert(h_key.to_string(), h_val.to_str().unwrap().to_string());
+                let resp = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+                _ = socket.write_all(resp.as_bytes()).await;
+            }
+        }).await;
+        format!("http://{}/graphql", addr)
+    }
+
+    #[test]
+    fn parse_outcome() {
+        let outcome = GraphQLOutcome::parse(br#"{"data":null,"errors":[{"message":"denied","path":["a",1]}]}"#).unwrap();
+        assert_eq!((outcome.get_errors(), outcome.has_data(), outcome.is_partial()), (1, false, false));
+        assert_eq!((outcome.get_message(), outcome.get_path()), (Some("denied"), Some("a.1")));
+        let outcome = GraphQLOutcome::parse(br#"{"data":{"a":1},"errors":[]}"#).unwrap();
+        assert_eq!((outcome.get_errors(), outcome.has_data(), outcome.get_message()), (0, true, None));
+        assert!(GraphQLOutcome::parse(b"oops").is_err());
+    }
+
+    #[tokio::test]
+    async fn detect_errors() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = graphql_server().await;
+        let query = |query: &str| GraphQLSampler::<()>::new(query, &url, query, Default::default(), None);
+
+        let re = query("ok").run(&mut ctx).await;
+        assert!(re.is_success());
+        assert_eq!(re.get_graphql().map(|g| g.get_errors()), Some(0));
+
+        let re = query("partial").run(&mut ctx).await;
+        assert_eq!((re.is_success(), re.get_failure_message()), (false, Some("no user at user.0.name")));
+        let outcome = re.get_graphql().unwrap();
+        assert_eq!((outcome.get_errors(), outcome.is_partial()), (2, true));
+        assert!(query("partial").error_policy(ErrorPolicy::AllowPartial).run(&mut ctx).await.is_success());
+
+        let re = query("failed").error_policy(ErrorPolicy::AllowPartial).run(&mut ctx).await;
+        assert_eq!((re.is_success(), re.get_failure_message()), (false, Some("syntax error")));
+        let re = query("failed").error_policy(ErrorPolicy::Ignore).run(&mut ctx).await;
+        assert!(re.is_success());
+        assert_eq!(re.get_graphql().map(|g| g.has_data()), Some(false));
+
+        let re = query("broken").run(&mut ctx).await;
+        assert!(!re.is_success());
+        assert!(re.get_failure_message().unwrap().starts_with("invalid GraphQL response"));
+        assert!(re.get_graphql().is_none());
+
+        // the errors are found whatever is kept of the body
+        for mode in [ResponseMode::Discard, ResponseMode::Hash, ResponseMode::Truncate(3)] {
+            let re = query("partial").response_mode(mode).run(&mut ctx).await;
+            assert_eq!(re.get_failure_message(), Some("no user at user.0.name"), "{:?}", mode);
+            assert!(!re.response_result().unwrap().response_data().contains("no user"));
+        }
+    }
+
+    /// Answer one operation with what the server got of it. A persisted query
+    /// hash is only known once its query was sent.
+    fn answer(op: &Value, known: &Mutex<HashSet<String>>) -> Value {
+        if let Some(hash) = op["extensions"]["persistedQuery"]["sha256Hash"].as_str() {
+            let mut known = known.lock().unwrap();
+            if op["query"].is_string() {
+                known.insert(hash.to_string());
+            } else if !known.contains(hash) {
+                return json!({"errors": [{"message": "PersistedQueryNotFound"}]});
+            }
+        }
+        json!({"data": {"query": op["query"], "operationName": op["operationName"], "variables": op["variables"]}})
+    }
+
+    /// A server echoing the operations it gets by POST or GET.
+    async fn echo_server() -> String {
+        let known = Arc::new(Mutex::new(HashSet::new()));
+        let addr = spawn_server(move |mut socket| {
+            let known = known.clone();
+            async move {
+                while let Some(req) = read_request(&mut socket).await {
+                    let resp = if req.method() == "GET" {
+                        let target = req.target();
+                        let url = Url::parse(&format!("http://localhost{}", target)).unwrap();
+                        let mut op = json!({"method": "GET"});
+                        for (k, v) in url.query_pairs() {
+                            op[k.as_ref()] = serde_json::from_str(&v).unwrap_or(Value::String(v.to_string()));
+                        }
+                        answer(&op, &known)
+                    } else {
+                        match serde_json::from_slice(&req.body).unwrap() {
+                            Value::Array(ops) => ops.iter().map(|op| answer(op, &known)).collect(),
+                            op => answer(&op, &known),
+                        }
+                    }.to_string();
+                    let resp = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", resp.len(), resp);
+                    _ = socket.write_all(resp.as_bytes()).await;
                 }
+            }
+        }).await;
+        format!("http://{}/graphql", addr)
+    }
 
-                let resp_body = r.text().await.unwrap_or("".to_string());
-
-                RecordData::new(
-                    start_send_timestamp.timestamp_millis() as u128,
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    self.label.clone(),
-                    code,
-                    resp_msg.into(),
-                    "".to_string(),
-                    data_type,
-                    success,
-                    fail_msg,
-                    resp_body.len() as u64,
-                    self.request_size() as u64,
-                    0,
-                    0,
-                    self.endpoint.clone(),
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    0,
-                    0,
-                    Some(ResponseResult::new(resp_headers, resp_body)),
-                )
-            },
-            Err(e) => {
-                error!("failed! --> {}", e.to_string());
-                RecordData::new(
-                    start_send_timestamp.timestamp_millis() as u128,
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    self.label.clone(),
-                    0,
-                    "no data".to_string(),
-                    "".to_string(),
-                    "no data".to_string(),
-                    false,
-                    Some(e.to_string()),
-                    0u64,
-                    self.request_size() as u64,
-                    0,
-                    0,
-                    self.endpoint.clone(),
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    0,
-                    0,
-                    None,
-                )
-            },
+    #[tokio::test]
+    async fn transports() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = echo_server().await;
+        let data = |re: &crate::record::RecordData| serde_json::from_str::<Value>(re.response_result().unwrap().response_data()).unwrap();
+        let query = "query Me { me } query You { you }";
+
+        let re = GraphQLSampler::new("named", &url, query, Default::default(), Some(json!({"id": 1})))
+            .operation_name("You")
+            .run(&mut ctx).await;
+        assert_eq!(data(&re)["data"], json!({"query": query, "operationName": "You", "variables": {"id": 1}}));
+
+        let re = GraphQLSampler::new("get", &url, query, Default::default(), Some(json!({"id": 1})))
+            .operation_name("Me")
+            .transport(Transport::Get)
+            .run(&mut ctx).await;
+        assert!(re.get_url().contains("operationName=Me"));
+        assert_eq!(data(&re)["data"], json!({"query": query, "operationName": "Me", "variables": {"id": 1}}));
+
+        let re = GraphQLSampler::new("batch", &url, "{ a }", Default::default(), None::<Value>)
+            .batch("{ b }", None)
+            .operation_name("B")
+            .transport(Transport::Get)
+            .run(&mut ctx).await;
+        assert!(re.is_success());
+        assert_eq!(data(&re)[1]["data"], json!({"query": "{ b }", "operationName": "B", "variables": null}));
+        assert_eq!(re.get_graphql().unwrap().get_errors(), 0);
+    }
+
+    #[tokio::test]
+    async fn persisted_queries() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = echo_server().await;
+        for transport in [Transport::Post, Transport::Get] {
+            let query = format!("{{ {:?} }}", transport);
+            let apq = GraphQLSampler::new("apq", &url, &query, Default::default(), None::<Value>)
+                .transport(transport)
+                .persisted_queries(true);
+            let re = apq.run(&mut ctx).await;
+            assert!(re.is_success(), "{:?}", re.get_failure_message());
+            let subs: Vec<&str> = re.sub_results().iter().map(|s| s.get_label()).collect();
+            assert_eq!(subs, ["apq-0", "apq-1"]);
+            assert!(re.sub_results()[0].response_result().unwrap().response_data().contains("PersistedQueryNotFound"));
+            assert_eq!(re.get_bytes(), re.sub_results().iter().map(|s| s.get_bytes()).sum::<u64>());
+
+            // the hash alone once the server knows it
+            let re = apq.run(&mut ctx).await;
+            assert!(re.is_success());
+            assert!(re.sub_results().is_empty());
+            assert!(re.response_result().unwrap().response_data().contains("\"query\":null"));
         }
 
+        // the miss is found in a body not kept
+        let re = GraphQLSampler::new("apq", &url, "{ Hash }", Default::default(), None::<Value>)
+            .persisted_queries(true)
+            .response_mode(ResponseMode::Hash)
+            .run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.sub_results().len(), 2);
+        assert_eq!(re.sub_results()[0].response_result().unwrap().response_data().len(), 64);
     }
-}
 
+    #[tokio::test]
+    async fn run_document_operation() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        ctx.vars_mut().set("email", "li@dao.dev");
+        let url = echo_server().await;
+        let doc = GraphQLDocument::parse("query Me { me { ...F } } mutation Login($email: String) { login(email: $email) } fragment F on User { id }").unwrap();
+        let vars = json!({"email": "${email}", "remember": true});
+        let re = DynamicGraphQLSampler::from_operation("login", &url, doc.operation("Login").unwrap(), Default::default(), Some(vars))
+            .run(&mut ctx).await;
+        let data: Value = serde_json::from_str(re.response_result().unwrap().response_data()).unwrap();
+        assert_eq!(data["data"]["operationName"], "Login");
+        assert_eq!(data["data"]["variables"], json!({"email": "li@dao.dev", "remember": true}));
+        assert_eq!(data["data"]["query"], "mutation Login($email: String) { login(email: $email) }");
+    }
+}
@@ -0,0 +1,270 @@
+use std::{collections::{HashMap, HashSet}, error::Error, fs, path::Path, sync::OnceLock};
+
+use regex::Regex;
+use tracing::*;
+
+/// Type of a GraphQL operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+/// One operation of a [`GraphQLDocument`], with the fragments it uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphQLOperation {
+    kind: OperationKind,
+    name: Option<String>,
+    query: String,
+}
+
+impl GraphQLOperation {
+    pub fn get_kind(&self) -> OperationKind {
+        self.kind
+    }
+
+    /// `None` for an anonymous operation.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The operation followed by the fragments it spreads, directly or not.
+    pub fn get_query(&self) -> &str {
+        &self.query
+    }
+}
+
+/// The operations of a GraphQL document.
+#[derive(Debug, Clone, Default)]
+pub struct GraphQLDocument {
+    operations: Vec<GraphQLOperation>,
+}
+
+impl GraphQLDocument {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&source)
+    }
+
+    /// Split a document into its operations. Type system definitions are skipped.
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut operations = Vec::new();
+        let mut fragments = HashMap::new();
+        for definition in definitions(source)? {
+            let (keyword, rest) = match definition.find(|c: char| !c.is_ascii_alphabetic()) {
+                Some(0) => ("query", definition),
+                Some(i) => definition.split_at(i),
+                None => (definition, ""),
+            };
+            let name = rest.trim_start();
+            let name = &name[..name.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(name.len())];
+            let kind = match keyword {
+                "query" => OperationKind::Query,
+                "mutation" => OperationKind::Mutation,
+                "subscription" => OperationKind::Subscription,
+                "fragment" => {
+                    fragments.insert(name.to_string(), definition);
+                    continue;
+                },
+                _ => {
+                    debug!("skip definition {} {}", keyword, name);
+                    continue;
+                },
+            };
+            let name = (!name.is_empty()).then(|| name.to_string());
+            operations.push((kind, name, definition));
+        }
+        let operations = operations.into_iter()
+            .map(|(kind, name, definition)| {
+                let mut query = definition.to_string();
+                for fragment in used_fragments(definition, &fragments)? {
+                    query.push_str("\n\n");
+                    query.push_str(fragment);
+                }
+                Ok(GraphQLOperation { kind, name, query })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Self { operations })
+    }
+
+    pub fn operations(&self) -> &[GraphQLOperation] {
+        &self.operations
+    }
+
+    pub fn operation(&self, name: &str) -> Option<&GraphQLOperation> {
+        self.operations.iter().find(|op| op.name.as_deref() == Some(name))
+    }
+}
+
+/// Keywords starting a definition.
+const KEYWORDS: [&str; 13] = [
+    "query", "mutation", "subscription", "fragment", "schema", "scalar", "type",
+    "interface", "union", "enum", "input", "directive", "extend",
+];
+
+/// The top level definitions of a document, comments and strings skipped
+/// when looking for their braces. A type system definition without braces,
+/// like `scalar Date`, ends where the next definition starts.
+fn definitions(source: &str) -> Result<Vec<&str>, Box<dyn Error>> {
+    let bytes = source.as_bytes();
+    let mut definitions = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut parens = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                i = source[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                continue;
+            },
+            b'"' if source[i..].starts_with("\"\"\"") => {
+                let end = source[i + 3..].find("\"\"\"").ok_or("unterminated block string")?;
+                i += 3 + end + 3;
+                continue;
+            },
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if i >= bytes.len() {
+                    return Err("unterminated string".into());
+                }
+            },
+            b'(' => {
+                start.get_or_insert(i);
+                parens += 1;
+            },
+            b')' => {
+                parens = parens.checked_sub(1).ok_or("unexpected )")?;
+            },
+            b'{' => {
+                // the selection set of an anonymous query after a `scalar`, a `union` or a `directive`
+                if let Some(s) = start.filter(|&s| depth == 0 && parens == 0 && without_braces(&source[s..i])) {
+                    definitions.push(source[s..i].trim());
+                    start = None;
+                }
+                start.get_or_insert(i);
+                depth += 1;
+            },
+            b'}' => {
+                depth = depth.checked_sub(1).ok_or("unexpected }")?;
+                if depth == 0 && parens == 0 {
+                    definitions.push(source[start.take().unwrap_or(i)..=i].trim());
+                }
+            },
+            c if depth == 0 && parens == 0 && (c.is_ascii_alphabetic() || c == b'_') => {
+                let end = source[i..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).map_or(bytes.len(), |n| i + n);
+                if let Some(s) = start.filter(|&s| KEYWORDS.contains(&&source[i..end]) && type_system(&source[s..i])) {
+                    definitions.push(source[s..i].trim());
+                    start = None;
+                }
+                start.get_or_insert(i);
+                i = end;
+                continue;
+            },
+            c if depth == 0 && !c.is_ascii_whitespace() && c != b',' => {
+                start.get_or_insert(i);
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    match start {
+        _ if depth > 0 || parens > 0 => return Err("unterminated definition".into()),
+        Some(s) if type_system(&source[s..]) => definitions.push(source[s..].trim()),
+        Some(_) => return Err("unterminated definition".into()),
+        None => {},
+    }
+    Ok(definitions)
+}
+
+/// The keyword of a definition, after `extend` if it extends a type.
+fn keyword(definition: &str) -> Option<&str> {
+    let mut words = definition.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).filter(|w| !w.is_empty());
+    match words.next() {
+        Some("extend") => words.next(),
+        keyword => keyword,
+    }
+}
+
+/// Whether a definition is of the type system, which may have no braces.
+fn type_system(definition: &str) -> bool {
+    keyword(definition).is_some_and(|k| !["query", "mutation", "subscription", "fragment"].contains(&k))
+}
+
+/// Whether a definition never has braces of its own.
+fn without_braces(definition: &str) -> bool {
+    keyword(definition).is_some_and(|k| ["scalar", "union", "directive"].contains(&k))
+}
+
+/// The fragments `definition` spreads, and the ones they spread, in the order
+/// they are met.
+fn used_fragments<'a>(definition: &str, fragments: &HashMap<String, &'a str>) -> Result<Vec<&'a str>, Box<dyn Error>> {
+    static SPREAD: OnceLock<Regex> = OnceLock::new();
+    let spread = SPREAD.get_or_init(|| Regex::new(r"\.\.\.\s*([_A-Za-z][_0-9A-Za-z]*)").unwrap());
+    let mut used = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![definition];
+    while let Some(text) = pending.pop() {
+        for name in spread.captures_iter(text).map(|c| c.get(1).unwrap().as_str()) {
+            if name == "on" || !seen.insert(name.to_string()) {
+                continue;
+            }
+            let fragment = *fragments.get(name).ok_or_else(|| format!("unknown fragment {}", name))?;
+            used.push(fragment);
+            pending.push(fragment);
+        }
+    }
+    Ok(used)
+}
+
+#[cfg(test)]
+mod gql_document_tests {
+    use super::{GraphQLDocument, OperationKind};
+
+    #[test]
+    fn split_operations() {
+        let source = r#"
+            # shared fields { not a brace }
+            fragment User on User { id name ...Avatar }
+            fragment Avatar on User { avatar(size: 64) }
+            fragment Unused on User { email }
+
+            query Me { me { ...User } }
+
+            mutation Rename($name: String = "a } b") {
+                rename(name: $name) { ... on User { ...User } }
+            }
+
+            scalar Date
+            union Result = User | Error
+            directive @cached(ttl: Int = 60) on FIELD | QUERY
+            extend type User @key(fields: "id")
+
+            subscription OnMessage { message { text } }
+
+            type Ignored { id: ID }
+        "#;
+        let doc = GraphQLDocument::parse(source).unwrap();
+        let names: Vec<_> = doc.operations().iter().map(|op| (op.get_kind(), op.get_name().unwrap())).collect();
+        assert_eq!(names, [(OperationKind::Query, "Me"), (OperationKind::Mutation, "Rename"), (OperationKind::Subscription, "OnMessage")]);
+        assert_eq!(
+            doc.operation("Me").unwrap().get_query(),
+            "query Me { me { ...User } }\n\nfragment User on User { id name ...Avatar }\n\nfragment Avatar on User { avatar(size: 64) }",
+        );
+        assert!(doc.operation("Rename").unwrap().get_query().contains("\"a } b\""));
+        assert!(!doc.operation("OnMessage").unwrap().get_query().contains("fragment"));
+
+        let anonymous = GraphQLDocument::parse("{ me { id } }").unwrap();
+        assert_eq!(anonymous.operations()[0].get_name(), None);
+        let after_scalar = GraphQLDocument::parse("scalar Date\n{ me { id } }\nunion U = A | B").unwrap();
+        assert_eq!(after_scalar.operations()[0].get_query(), "{ me { id } }");
+        assert!(GraphQLDocument::parse("query A").is_err());
+        assert!(GraphQLDocument::parse("query A { a ").is_err());
+        assert!(GraphQLDocument::parse("query A { ...Missing }").is_err());
+    }
+}
@@ -1,10 +1,13 @@
 use std::fmt;
-use std::{collections::HashMap, error::Error};
+use std::{error::Error, sync::Arc, time::Duration};
 
-use tracing::*;
 use async_trait::async_trait;
 
-use crate::{Sampler, record::{RecordData, ResponseResult}};
+use crate::{Sampler, Assertion, Extractor, record::RecordData, assertions::{self, AssertionRef}, extractors::{self, ExtractorRef}, context::VUContext, config::header::{self, HeaderManager}};
+
+use reqwest::header::CONTENT_TYPE;
+
+use super::{exchange::{self, ExchangeOptions, RedirectPolicy, ResponseMode}, body::Body, client::HttpVersion, proxy::ProxyConfig};
 
 pub type HeaderMap = reqwest::header::HeaderMap;
 pub type HeaderValue = reqwest::header::HeaderValue;
@@ -16,7 +19,10 @@ pub struct HttpSampler {
     url: String,
     method: Method,
     headers: HeaderMap,
-    body: Option<String>,
+    body: Option<Body>,
+    assertions: Vec<AssertionRef>,
+    extractors: Vec<ExtractorRef>,
+    options: ExchangeOptions,
 }
 
 #[derive(Debug)]
@@ -40,143 +46,218 @@ impl Error for RumeterErr {
     
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Method {
     GET,
     POST,
     PUT,
+    DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS,
+    TRACE,
+    CONNECT,
+    /// any other method token, e.g. the WebDAV ones like PROPFIND or MKCOL.
+    Extension(String),
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl Method {
     pub fn from(m: &str) -> Result<Self, Box<dyn Error>> {
-        if m.eq_ignore_ascii_case("get") {
-            Ok(Method::GET)
-        } else if m.eq_ignore_ascii_case("post") {
-            Ok(Method::POST)
-        } else if m.eq_ignore_ascii_case("PUT") {
-            Ok(Method::PUT)
-        } else {
-            Err(Box::new(RumeterErr::new("method not supported")))
-        }
+        let method = match m.to_ascii_uppercase().as_str() {
+            "GET" => Method::GET,
+            "POST" => Method::POST,
+            "PUT" => Method::PUT,
+            "DELETE" => Method::DELETE,
+            "PATCH" => Method::PATCH,
+            "HEAD" => Method::HEAD,
+            "OPTIONS" => Method::OPTIONS,
+            "TRACE" => Method::TRACE,
+            "CONNECT" => Method::CONNECT,
+            // extension methods are case-sensitive, keep them as given
+            _ => match reqwest::Method::from_bytes(m.as_bytes()) {
+                Ok(_) => Method::Extension(m.to_string()),
+                Err(_) => return Err(Box::new(RumeterErr::new("method not supported"))),
+            },
+        };
+        Ok(method)
     }
 
-    pub fn len(&self) -> u32{
+    pub fn as_str(&self) -> &str {
         match self {
-            Method::GET => 3,
-            Method::POST => 4,
-            Method::PUT => 3,
+            Method::GET => "GET",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::PATCH => "PATCH",
+            Method::HEAD => "HEAD",
+            Method::OPTIONS => "OPTIONS",
+            Method::TRACE => "TRACE",
+            Method::CONNECT => "CONNECT",
+            Method::Extension(m) => m,
         }
     }
+
+    pub fn len(&self) -> u32{
+        self.as_str().len() as u32
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&Method> for reqwest::Method {
+    fn from(m: &Method) -> Self {
+        // every variant has been validated as a method token
+        reqwest::Method::from_bytes(m.as_str().as_bytes()).unwrap()
+    }
 }
 
 impl HttpSampler {
     pub fn new(label: &str, url: &str, method: Method, headers: HeaderMap, body: Option<String>) -> Self {
-        Self { label: label.to_string(), url: url.to_string(), method, headers, body }
+        let body = body.map(Body::Text);
+        Self { label: label.to_string(), url: url.to_string(), method, headers, body, assertions: Vec::new(), extractors: Vec::new(), options: ExchangeOptions::default() }
     }
 
-    fn request_size(&self) -> u32 {
-        self.request_line_size() + self.request_headers_size() + self.request_body_size()
+    /// Replace the body, e.g. with form parameters or a multipart body, see [`Body`].
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = Some(body.into());
+        self
     }
 
-    fn request_headers_size(&self) -> u32 {
-        let mut size = 0u32;
-        for (key, value) in self.headers.clone() {
-            match key {
-                Some(header_name) => {
-                    size = size + (header_name.to_string().len() + value.len() + ":\r\n".len()) as u32;
-                },
-                None => {},
-            }
-        }
-        size
+    pub fn assertion(mut self, assertion: impl Assertion + 'static) -> Self {
+        self.options.body_needed |= assertion.reads_body();
+        self.options.expected_codes.extend_from_slice(assertion.expected_codes());
+        self.assertions.push(Arc::new(assertion));
+        self
+    }
+
+    pub fn extractor(mut self, extractor: impl Extractor + 'static) -> Self {
+        self.options.body_needed |= extractor.reads_body();
+        self.extractors.push(Arc::new(extractor));
+        self
+    }
+
+    /// Time to open a new connection, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeouts = self.options.timeouts.connect(timeout);
+        self
+    }
+
+    /// Time to wait for the response head and each piece of the body.
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeouts = self.options.timeouts.response(timeout);
+        self
     }
 
-    fn request_line_size(&self) -> u32 {
-        self.method.len() + (self.url.len() + "  HTTP/1.1\r\n".len()) as u32
+    /// Time of the whole exchange.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeouts = self.options.timeouts.total(timeout);
+        self
     }
 
-    fn request_body_size(&self) -> u32 {
-        (self.body.clone().unwrap_or("".to_string()).len() + "\r\n".len()) as u32 
+    /// Which redirects to follow, all of them up to 10 by default.
+    pub fn redirect(mut self, redirect: RedirectPolicy) -> Self {
+        self.options.redirect = redirect;
+        self
     }
+
+    /// Keep each response of the followed redirects as a sub-result, labelled
+    /// `label-0`, `label-1` and so on.
+    pub fn redirect_samples(mut self, redirect_samples: bool) -> Self {
+        self.options.redirect_samples = redirect_samples;
+        self
+    }
+
+    /// Protocol of this sampler, over the one of the [`super::client::ClientConfig`].
+    pub fn http_version(mut self, http_version: HttpVersion) -> Self {
+        self.options.http_version = Some(http_version);
+        self
+    }
+
+    /// Proxy of this sampler, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.options.proxy = Some(proxy);
+        self
+    }
+
+    /// What to keep of the response body, only what the assertions and
+    /// extractors need by default.
+    pub fn response_mode(mut self, response_mode: ResponseMode) -> Self {
+        self.options.response_mode = response_mode;
+        self
+    }
+}
+
+/// Put the headers of the sampler over the header layers of the virtual user,
+/// and render the templates of the values. A value that is no longer a valid
+/// header after rendering is sent unchanged.
+pub(crate) fn render_headers(headers: &HeaderMap, ctx: &mut VUContext) -> HeaderMap {
+    let own = HeaderManager::from(headers.clone());
+    let merged = header::merge(ctx.header_layers().iter().chain([&own]));
+    let mut out = HeaderMap::with_capacity(merged.len());
+    for (name, value) in &merged {
+        let value = value.to_str().ok()
+            .filter(|v| v.contains("${"))
+            .and_then(|v| HeaderValue::from_str(&ctx.render(v)).ok())
+            .unwrap_or_else(|| value.clone());
+        out.append(name.clone(), value);
+    }
+    out
 }
 
 #[async_trait]
 impl Sampler for HttpSampler {
-    async fn run(&self) -> RecordData{
-        let client = reqwest::Client::new();
-        let s = self.clone();
-        let start_send_timestamp = chrono::Local::now();
-        let resp = match self.method {
-            Method::GET => client.get(s.url.clone()).headers(s.headers.clone()).send().await,
-            Method::POST => client.post(s.url.clone()).headers(s.headers.clone()).body(s.body.clone().unwrap()).send().await,
-            Method::PUT => client.put(s.url.clone()).headers(s.headers.clone()).body(s.body.clone().unwrap()).send().await,
-        };
-        
-        let finish_send_timestamp = chrono::Local::now();
-        match resp {
-            Ok(r) => {
-                let data_type = String::from("text");
-                let code = r.status().as_u16();
-                let resp_msg = r.status().canonical_reason().unwrap_or("Unknown");
-                let success = code < 400u16;
-                let fail_msg = if success {
-                    None
-                } else {
-                    Some(resp_msg.to_string())
-                };
-                let mut resp_headers: HashMap<String, String> = HashMap::new();
-                for (h_key, h_val) in r.headers() {
-                    resp_headers.insert(h_key.to_string(), h_val.to_str().unwrap().to_string());
-                }
-
-                let resp_body = r.text().await.unwrap_or("".to_string());
-
-                RecordData::new(
-                    start_send_timestamp.timestamp_millis() as u128,
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    self.label.clone(),
-                    code,
-                    resp_msg.into(),
-                    "".to_string(),
-                    data_type,
-                    success,
-                    fail_msg,
-                    resp_body.len() as u64,
-                    s.request_size() as u64,
-                    0,
-                    0,
-                    s.url,
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    0,
-                    0,
-                    Some(ResponseResult::new(resp_headers, resp_body)),
-                )
+    fn label(&self) -> &str {
+        &self.label
+    }
 
+    async fn run(&self, ctx: &mut VUContext) -> RecordData{
+        let defaults = ctx.http_defaults();
+        let url = ctx.render(&self.url);
+        let url = defaults.url(&url, ctx);
+        let mut headers = render_headers(&self.headers, ctx);
+        let body = match &self.body {
+            Some(body) => match body.render(ctx).await {
+                Ok(body) => Some(body),
+                Err(e) => return exchange::failed_record(chrono::Local::now(), &self.label, &url, e, 0),
             },
-            Err(e) => {
-                error!("failed! --> {}", e.to_string());
-                RecordData::new(
-                    start_send_timestamp.timestamp_millis() as u128,
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    self.label.clone(),
-                    0,
-                    "no data".to_string(),
-                    "".to_string(),
-                    "no data".to_string(),
-                    false,
-                    Some(e.to_string()),
-                    0u64,
-                    s.request_size() as u64,
-                    0,
-                    0,
-                    s.url,
-                    (finish_send_timestamp - start_send_timestamp).num_milliseconds() as u64,
-                    0,
-                    0,
-                    None,
-                )
-            },
+            None => None,
+        };
+        let mut req = ctx.client().request((&self.method).into(), &url);
+        if let Some((bytes, content_type)) = body {
+            if let Some(content_type) = content_type.and_then(|c| HeaderValue::from_str(&c).ok()) {
+                // a multipart boundary has to be the one of the body
+                if matches!(self.body, Some(Body::Multipart(_))) || !headers.contains_key(CONTENT_TYPE) {
+                    headers.insert(CONTENT_TYPE, content_type);
+                }
+            }
+            req = req.body(bytes);
         }
+        let req = req.headers(headers);
+        let mut re = exchange::exchange(ctx, &self.label, &url, req, &self.options).await;
+        extractors::apply(&self.extractors, &re, ctx);
+        assertions::apply(&self.assertions, &mut re);
+        re
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::Method;
+
+    #[test]
+    fn parse_method() {
+        assert_eq!(Method::from("delete").unwrap(), Method::DELETE);
+        assert_eq!(Method::from("Patch").unwrap(), Method::PATCH);
+        assert_eq!(Method::from("PROPFIND").unwrap(), Method::Extension("PROPFIND".to_string()));
+        assert_eq!(Method::from("PROPFIND").unwrap().len(), 8);
+        assert_eq!(Method::from("OPTIONS").unwrap().len(), 7);
+        assert!(Method::from("BAD METHOD").is_err());
+        assert!(Method::from("").is_err());
+    }
+}
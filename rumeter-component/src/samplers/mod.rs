@@ -1,2 +1,14 @@
+pub mod client;
+pub mod tls;
+pub mod proxy;
+pub mod exchange;
 pub mod http;
-pub mod gql;
\ No newline at end of file
+pub mod body;
+pub mod gql;
+pub mod gql_document;
+pub mod subscription;
+pub mod websocket;
+pub mod tcp;
+pub(crate) mod ws;
+#[cfg(test)]
+pub(crate) mod test_server;
@@ -0,0 +1,472 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+use crate::{Sampler, Assertion, Extractor, record::{RecordData, ResponseResult}, assertions::{self, AssertionRef}, extractors::{self, ExtractorRef}, context::VUContext};
+
+use super::{http::{HeaderMap, render_headers}, gql::RequestBody, gql_document::GraphQLOperation, proxy::ProxyConfig, ws::{self, WsStream}};
+
+pub use crate::record::SubscriptionStats;
+
+/// Message protocol of a GraphQL subscription over a websocket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WsProtocol {
+    /// `graphql-transport-ws` of graphql-ws.
+    #[default]
+    GraphQLTransportWs,
+    /// `graphql-ws` of the legacy subscriptions-transport-ws.
+    SubscriptionsTransportWs,
+}
+
+impl WsProtocol {
+    fn name(&self) -> &'static str {
+        match self {
+            WsProtocol::GraphQLTransportWs => "graphql-transport-ws",
+            WsProtocol::SubscriptionsTransportWs => "graphql-ws",
+        }
+    }
+
+    fn subscribe(&self) -> &'static str {
+        match self {
+            WsProtocol::GraphQLTransportWs => "subscribe",
+            WsProtocol::SubscriptionsTransportWs => "start",
+        }
+    }
+
+    fn next(&self) -> &'static str {
+        match self {
+            WsProtocol::GraphQLTransportWs => "next",
+            WsProtocol::SubscriptionsTransportWs => "data",
+        }
+    }
+
+    fn unsubscribe(&self) -> &'static str {
+        match self {
+            WsProtocol::GraphQLTransportWs => "complete",
+            WsProtocol::SubscriptionsTransportWs => "stop",
+        }
+    }
+}
+
+/// Subscribe to a GraphQL subscription over a websocket and hold it for a
+/// time or a number of events, or until the server completes it. The whole
+/// subscription is one sample: its latency is the time to the first event,
+/// its bytes are the ones of the websocket, and its response data is the
+/// array of the event payloads when an assertion or extractor reads it.
+#[derive(Clone)]
+pub struct GraphQLSubscriptionSampler<T: Serialize + Clone + Send + Sync> {
+    label: String,
+    endpoint: String,
+    headers: HeaderMap,
+    body: RequestBody<T>,
+    protocol: WsProtocol,
+    init_payload: Option<Value>,
+    duration: Option<Duration>,
+    max_events: Option<usize>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+    ack_timeout: Duration,
+    event_samples: bool,
+    body_needed: bool,
+    assertions: Vec<AssertionRef>,
+    extractors: Vec<ExtractorRef>,
+}
+
+impl <T: Serialize + Clone + Send + Sync> GraphQLSubscriptionSampler<T> {
+    /// `endpoint` is a `ws://` or `wss://` url, `http` and `https` are turned into them.
+    pub fn new(label: &str, endpoint: &str, query: &str, headers: HeaderMap, vars: Option<T>) -> Self {
+        Self {
+            label: label.to_string(),
+            endpoint: endpoint.to_string(),
+            headers,
+            body: RequestBody { query: query.to_string(), operation_name: None, variables: vars },
+            protocol: WsProtocol::default(),
+            init_payload: None,
+            duration: None,
+            max_events: None,
+            connect_timeout: None,
+            proxy: None,
+            ack_timeout: Duration::from_secs(10),
+            event_samples: false,
+            body_needed: false,
+            assertions: Vec::new(),
+            extractors: Vec::new(),
+        }
+    }
+
+    /// `graphql-transport-ws` by default.
+    pub fn protocol(mut self, protocol: WsProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Payload of `connection_init`, like an auth token.
+    pub fn init_payload(mut self, init_payload: Value) -> Self {
+        self.init_payload = Some(init_payload);
+        self
+    }
+
+    /// Stop the subscription after this time.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Stop the subscription after this many events.
+    pub fn max_events(mut self, max_events: usize) -> Self {
+        self.max_events = Some(max_events);
+        self
+    }
+
+    /// Time to open the connection, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Proxy of this sampler, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Time to wait for `connection_ack`, 10 seconds by default.
+    pub fn ack_timeout(mut self, timeout: Duration) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    /// Keep each event as a sub-result, labelled `label-n`.
+    pub fn event_samples(mut self, event_samples: bool) -> Self {
+        self.event_samples = event_samples;
+        self
+    }
+
+    pub fn assertion(mut self, assertion: impl Assertion + 'static) -> Self {
+        self.body_needed |= assertion.reads_body();
+        self.assertions.push(Arc::new(assertion));
+        self
+    }
+
+    pub fn extractor(mut self, extractor: impl Extractor + 'static) -> Self {
+        self.body_needed |= extractor.reads_body();
+        self.extractors.push(Arc::new(extractor));
+        self
+    }
+
+    /// Wait for `connection_ack`, answering pings.
+    async fn ack(&self, stream: &mut WsStream) -> Result<(), String> {
+        loop {
+            let msg = match tokio::time::timeout(self.ack_timeout, stream.next()).await {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(Some(Err(e))) => return Err(e.to_string()),
+                Ok(None) => return Err("connection closed before connection_ack".to_string()),
+                Err(_) => return Err("no connection_ack".to_string()),
+            };
+            let msg = match msg {
+                Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap_or_default(),
+                Message::Close(_) => return Err("connection closed before connection_ack".to_string()),
+                _ => continue,
+            };
+            match msg["type"].as_str() {
+                Some("connection_ack") => return Ok(()),
+                Some("ping") => send(stream, json!({"type": "pong"})).await?,
+                Some("connection_error") => return Err(format!("connection_error: {}", msg["payload"])),
+                _ => {},
+            }
+        }
+    }
+}
+
+impl GraphQLSubscriptionSampler<Value> {
+    /// A sampler running a subscription of a [`super::gql_document::GraphQLDocument`].
+    pub fn from_operation(label: &str, endpoint: &str, operation: &GraphQLOperation, headers: HeaderMap, vars: Option<Value>) -> Self {
+        let mut sampler = Self::new(label, endpoint, operation.get_query(), headers, vars);
+        sampler.body.operation_name = operation.get_name().map(str::to_string);
+        sampler
+    }
+}
+
+async fn send(stream: &mut WsStream, msg: Value) -> Result<(), String> {
+    stream.send(Message::Text(msg.to_string())).await.map_err(|e| e.to_string())
+}
+
+/// Message of the first error of an `error` payload, an array in
+/// graphql-transport-ws and an object in the legacy protocol.
+fn error_message(payload: &Value) -> String {
+    let first = payload.as_array().and_then(|errors| errors.first()).unwrap_or(payload);
+    match &first["message"] {
+        Value::String(message) => message.clone(),
+        _ => first.to_string(),
+    }
+}
+
+#[async_trait]
+impl <T: Serialize + Clone + Send + Sync> Sampler for GraphQLSubscriptionSampler<T> {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let start_send_timestamp = chrono::Local::now();
+        let defaults = ctx.http_defaults();
+        let endpoint = ctx.render(&self.endpoint);
+        let endpoint = ws::ws_url(&defaults.url(&endpoint, ctx));
+        let mut payload = serde_json::to_value(&self.body).unwrap_or_default();
+        ctx.render_json(&mut payload);
+        let headers = render_headers(&self.headers, ctx);
+        let connect_timeout = self.connect_timeout.or(defaults.get_timeouts().get_connect());
+
+        let start = Instant::now();
+        let mut conn = match ws::connect(ctx, &endpoint, headers, &[self.protocol.name()], self.proxy.as_ref().or(defaults.get_proxy()), connect_timeout).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                let mut re = e.record(start_send_timestamp, &self.label, &endpoint);
+                re.elapsed(start.elapsed().as_millis() as u64);
+                return re;
+            },
+        };
+
+        let mut init = json!({"type": "connection_init"});
+        if let Some(init_payload) = &self.init_payload {
+            init["payload"] = init_payload.clone();
+        }
+        let acked = Instant::now();
+        let mut failure = match send(&mut conn.stream, init).await {
+            Ok(()) => self.ack(&mut conn.stream).await.err(),
+            Err(e) => Some(e),
+        };
+        let mut stats = SubscriptionStats { ack: acked.elapsed(), ..Default::default() };
+
+        let id = "1";
+        let mut payloads = Vec::new();
+        let mut events = Vec::new();
+        if failure.is_none() {
+            let subscribe = json!({"id": id, "type": self.protocol.subscribe(), "payload": payload});
+            failure = send(&mut conn.stream, subscribe).await.err();
+        }
+        let subscribed = Instant::now();
+        let end = self.duration.map(|d| subscribed + d);
+        // whether the client stops the subscription, instead of the server
+        let mut stopping = true;
+        while failure.is_none() && self.max_events.map_or(true, |max| stats.events < max) {
+            let next = match end {
+                Some(end) => match tokio::time::timeout_at(end.into(), conn.stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => break,
+                },
+                None => conn.stream.next().await,
+            };
+            let text = match next {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | None => {
+                    failure = Some("connection closed".to_string());
+                    stopping = false;
+                    break;
+                },
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    failure = Some(e.to_string());
+                    stopping = false;
+                    break;
+                },
+            };
+            let msg = serde_json::from_str::<Value>(&text).unwrap_or_default();
+            match msg["type"].as_str() {
+                Some(kind) if kind == self.protocol.next() && msg["id"] == id => {
+                    let since = subscribed.elapsed();
+                    stats.first_event.get_or_insert(since);
+                    stats.events += 1;
+                    stats.event_bytes += text.len() as u64;
+                    if self.event_samples {
+                        events.push((chrono::Local::now(), since, text.len() as u64, msg["payload"].to_string()));
+                    }
+                    if self.body_needed {
+                        payloads.push(msg["payload"].clone());
+                    }
+                },
+                Some("error") if msg["id"] == id => {
+                    failure = Some(error_message(&msg["payload"]));
+                    stopping = false;
+                },
+                Some("complete") if msg["id"] == id => {
+                    stopping = false;
+                    break;
+                },
+                Some("ping") => failure = send(&mut conn.stream, json!({"type": "pong"})).await.err(),
+                _ => {},
+            }
+        }
+        if stopping {
+            _ = send(&mut conn.stream, json!({"id": id, "type": self.protocol.unsubscribe()})).await;
+        }
+        if self.protocol == WsProtocol::SubscriptionsTransportWs {
+            _ = send(&mut conn.stream, json!({"type": "connection_terminate"})).await;
+        }
+        _ = conn.stream.close(None).await;
+        let elapsed = start.elapsed();
+
+        let data = if self.body_needed { Value::Array(payloads).to_string() } else { String::new() };
+        let mut re = RecordData::new(
+            start_send_timestamp.timestamp_millis() as u128,
+            elapsed.as_millis() as u64,
+            self.label.clone(),
+            conn.response_code,
+            conn.response_message.clone(),
+            "".to_string(),
+            "text".to_string(),
+            failure.is_none(),
+            failure,
+            conn.traffic.received(),
+            conn.traffic.sent(),
+            0,
+            0,
+            endpoint.clone(),
+            stats.first_event.map_or(elapsed, |first| subscribed - start + first).as_millis() as u64,
+            0,
+            conn.connect.as_millis() as u64,
+            Some(ResponseResult::new(conn.response_headers.clone(), data)),
+        );
+        re.tls(conn.tls);
+        re.body_size(stats.event_bytes);
+        re.decoded_body_size(stats.event_bytes);
+        re.subscription(Some(stats));
+        for (n, (timestamp, since, size, payload)) in events.into_iter().enumerate() {
+            let mut event = RecordData::new(
+                timestamp.timestamp_millis() as u128,
+                since.as_millis() as u64,
+                format!("{}-{}", self.label, n),
+                conn.response_code,
+                conn.response_message.clone(),
+                "".to_string(),
+                "text".to_string(),
+                true,
+                None,
+                size,
+                0,
+                0,
+                0,
+                endpoint.clone(),
+                since.as_millis() as u64,
+                0,
+                0,
+                Some(ResponseResult::new(Default::default(), payload)),
+            );
+            event.body_size(size);
+            event.decoded_body_size(size);
+            re.add_sub_result(event);
+        }
+        extractors::apply(&self.extractors, &re, ctx);
+        assertions::apply(&self.assertions, &mut re);
+        re
+    }
+}
+
+#[cfg(test)]
+mod subscription_tests {
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::{context::VUContext, Sampler, assertions::{TextPattern, response::ResponseTextAssertion}, samplers::test_server::{accept_ws, spawn_server}};
+
+    use super::{GraphQLSubscriptionSampler, WsProtocol};
+
+    /// A GraphQL server speaking both protocols. The query `count` gets 3 events
+    /// and completes, `tick` gets an event every 20ms until stopped, `fail` gets
+    /// an error.
+    async fn subscription_server() -> String {
+        let addr = spawn_server(|socket| async move {
+            let mut ws = accept_ws(socket).await;
+            let mut legacy = false;
+            while let Some(Ok(Message::Text(text))) = ws.next().await {
+                let msg: Value = serde_json::from_str(&text).unwrap();
+                let id = msg["id"].clone();
+                match msg["type"].as_str().unwrap() {
+                    "connection_init" => {
+                        legacy = msg.get("payload").is_some_and(|p| p["legacy"] == true);
+                        ws.send(Message::Text(json!({"type": "connection_ack"}).to_string())).await.unwrap();
+                        if legacy {
+                            ws.send(Message::Text(json!({"type": "ka"}).to_string())).await.unwrap();
+                        }
+                    },
+                    "subscribe" | "start" => {
+                        let next = if legacy { "data" } else { "next" };
+                        match msg["payload"]["query"].as_str().unwrap() {
+                            "count" => {
+                                for n in 0..3 {
+                                    let event = json!({"id": id, "type": next, "payload": {"data": {"count": n}}});
+                                    ws.send(Message::Text(event.to_string())).await.unwrap();
+                                }
+                                ws.send(Message::Text(json!({"id": id, "type": "complete"}).to_string())).await.unwrap();
+                            },
+                            "tick" => loop {
+                                tokio::time::sleep(Duration::from_millis(20)).await;
+                                let event = json!({"id": id, "type": next, "payload": {"data": {"tick": true}}});
+                                if ws.send(Message::Text(event.to_string())).await.is_err() {
+                                    return;
+                                }
+                                if let Ok(Some(Ok(Message::Text(_)))) = tokio::time::timeout(Duration::ZERO, ws.next()).await {
+                                    break;
+                                }
+                            },
+                            _ => {
+                                let error = json!({"id": id, "type": "error", "payload": [{"message": "not allowed"}]});
+                                ws.send(Message::Text(error.to_string())).await.unwrap();
+                            },
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }).await;
+        format!("ws://{}/graphql", addr)
+    }
+
+    #[tokio::test]
+    async fn hold_subscriptions() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = subscription_server().await;
+        let subscribe = |query: &str| GraphQLSubscriptionSampler::<()>::new(query, &url, query, Default::default(), None);
+
+        let re = subscribe("count")
+            .event_samples(true)
+            .assertion(ResponseTextAssertion::new(TextPattern::Substring("{\"data\":{\"count\":2}}".to_string())))
+            .run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.get_response_code(), 101);
+        let stats = re.get_subscription().unwrap();
+        assert_eq!(stats.get_events(), 3);
+        assert!(stats.get_first_event().is_some());
+        assert_eq!(re.sub_results().len(), 3);
+        assert_eq!(re.sub_results()[1].get_label(), "count-1");
+        assert_eq!(re.sub_results()[1].response_result().unwrap().response_data(), "{\"data\":{\"count\":1}}");
+        assert!(re.get_bytes() > stats.get_event_bytes());
+        assert!(re.get_sent_bytes() > 0);
+
+        let re = subscribe("tick")
+            .protocol(WsProtocol::SubscriptionsTransportWs)
+            .init_payload(json!({"legacy": true}))
+            .max_events(2)
+            .run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.get_subscription().unwrap().get_events(), 2);
+        assert_eq!(re.response_result().unwrap().response_data(), "");
+
+        let re = subscribe("tick").duration(Duration::from_millis(150)).run(&mut ctx).await;
+        assert!(re.is_success());
+        assert!((2..=8).contains(&re.get_subscription().unwrap().get_events()));
+        assert!(re.get_elapsed() >= 150);
+
+        let re = subscribe("fail").run(&mut ctx).await;
+        assert_eq!((re.is_success(), re.get_failure_message()), (false, Some("not allowed")));
+
+        let re = GraphQLSubscriptionSampler::<()>::new("refused", "ws://127.0.0.1:1/graphql", "count", Default::default(), None).run(&mut ctx).await;
+        assert!(!re.is_success());
+    }
+}
@@ -0,0 +1,394 @@
+use std::{collections::HashMap, error::Error, sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+use crate::{Sampler, Assertion, Extractor, record::{RecordData, ResponseResult, TIMEOUT_CODE}, assertions::{self, AssertionRef}, extractors::{self, ExtractorRef}, context::VUContext};
+
+use super::{client::TimeoutKind, exchange::ConnectError};
+
+/// What a [`TcpSampler`] sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// Sent as is.
+    Text(String),
+    /// A `${...}` template, rendered for every run.
+    Template(String),
+    Bytes(Vec<u8>),
+}
+
+impl Payload {
+    /// Bytes written as hex digits, whitespace ignored, like `"01 0a FF"`.
+    pub fn hex(digits: &str) -> Result<Self, Box<dyn Error>> {
+        let digits: Vec<u8> = digits.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        if !digits.len().is_multiple_of(2) {
+            return Err("odd number of hex digits".into());
+        }
+        let bytes = digits.chunks(2)
+            .map(|pair| {
+                let pair = std::str::from_utf8(pair).map_err(|_| "invalid hex digit")?;
+                u8::from_str_radix(pair, 16).map_err(|_| format!("invalid hex digits {}", pair))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Payload::Bytes(bytes))
+    }
+
+    fn bytes(&self, ctx: &mut VUContext) -> Vec<u8> {
+        match self {
+            Payload::Text(text) => text.as_bytes().to_vec(),
+            Payload::Template(template) => ctx.render(template).into_bytes(),
+            Payload::Bytes(bytes) => bytes.clone(),
+        }
+    }
+}
+
+/// Where the response of a [`TcpSampler`] ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadUntil {
+    /// A `\n`, kept in the response.
+    #[default]
+    Eol,
+    /// A number of bytes.
+    Length(usize),
+    /// A header of `size` bytes holding the length of the rest, the header
+    /// being kept in the response.
+    LengthPrefix { size: usize, big_endian: bool },
+    /// The timeout or the server closing the connection, never a failure.
+    Timeout,
+}
+
+impl ReadUntil {
+    /// Length of the response at the start of `buf` if it is all there, or an
+    /// error when the response is, or says it is, longer than `max`.
+    fn complete(&self, buf: &[u8], max: usize) -> Result<Option<usize>, ReadError> {
+        let too_large = || ReadError::Failed(format!("response larger than {} bytes", max));
+        let len = match *self {
+            ReadUntil::Eol => buf.iter().position(|&b| b == b'\n').map(|i| i + 1),
+            ReadUntil::Length(len) if len > max => return Err(too_large()),
+            ReadUntil::Length(len) => (buf.len() >= len).then_some(len),
+            ReadUntil::LengthPrefix { size, big_endian } => match buf.get(..size) {
+                Some(header) => {
+                    let mut bytes = header.to_vec();
+                    if !big_endian {
+                        bytes.reverse();
+                    }
+                    let len = bytes.iter()
+                        .try_fold(0usize, |len, &b| len.checked_mul(256)?.checked_add(b as usize))
+                        .and_then(|len| len.checked_add(size))
+                        .filter(|&len| len <= max)
+                        .ok_or_else(too_large)?;
+                    (buf.len() >= len).then_some(len)
+                },
+                None => None,
+            },
+            ReadUntil::Timeout => None,
+        };
+        match len {
+            Some(len) if len > max => Err(too_large()),
+            None if buf.len() > max => Err(too_large()),
+            len => Ok(len),
+        }
+    }
+}
+
+/// A connection kept by a virtual user, with what it read past the last response.
+struct TcpConnection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+/// The connections a virtual user keeps, by `host:port`.
+#[derive(Default)]
+struct TcpConnections(HashMap<String, TcpConnection>);
+
+/// Why a response was not read whole.
+enum ReadError {
+    Timeout,
+    Closed,
+    Failed(String),
+}
+
+/// Send a payload on a TCP connection and read the response. The connection
+/// is closed after the run unless [`TcpSampler::reuse_connection`] keeps it
+/// for the next runs of the virtual user.
+#[derive(Clone)]
+pub struct TcpSampler {
+    label: String,
+    host: String,
+    port: u16,
+    payload: Payload,
+    read_until: ReadUntil,
+    timeout: Duration,
+    connect_timeout: Option<Duration>,
+    reuse_connection: bool,
+    max_response_size: usize,
+    assertions: Vec<AssertionRef>,
+    extractors: Vec<ExtractorRef>,
+}
+
+impl TcpSampler {
+    /// `host` is a `${...}` template. The response ends at a `\n`, takes
+    /// at most 10 seconds and 16 MiB by default.
+    pub fn new(label: &str, host: &str, port: u16, payload: Payload) -> Self {
+        Self {
+            label: label.to_string(),
+            host: host.to_string(),
+            port,
+            payload,
+            read_until: ReadUntil::Eol,
+            timeout: Duration::from_secs(10),
+            connect_timeout: None,
+            reuse_connection: false,
+            max_response_size: 16 << 20,
+            assertions: Vec::new(),
+            extractors: Vec::new(),
+        }
+    }
+
+    pub fn read_until(mut self, read_until: ReadUntil) -> Self {
+        self.read_until = read_until;
+        self
+    }
+
+    /// Time to send the payload and read the response.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep the connection open for the next runs of the virtual user to the
+    /// same host and port.
+    pub fn reuse_connection(mut self, reuse: bool) -> Self {
+        self.reuse_connection = reuse;
+        self
+    }
+
+    /// Bytes a response may take, the sample failing past them.
+    pub fn max_response_size(mut self, size: usize) -> Self {
+        self.max_response_size = size;
+        self
+    }
+
+    pub fn assertion(mut self, assertion: impl Assertion + 'static) -> Self {
+        self.assertions.push(Arc::new(assertion));
+        self
+    }
+
+    pub fn extractor(mut self, extractor: impl Extractor + 'static) -> Self {
+        self.extractors.push(Arc::new(extractor));
+        self
+    }
+
+    async fn open(&self, addr: &str) -> Result<TcpStream, ConnectError> {
+        let connecting = TcpStream::connect(addr);
+        let stream = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connecting).await.map_err(|_| ConnectError::Timeout(TimeoutKind::Connect))?,
+            None => connecting.await,
+        };
+        stream.map_err(|e| ConnectError::Failed(format!("cannot connect to {}: {}", addr, e)))
+    }
+}
+
+/// Read from `conn` until the response is complete, returning it and the
+/// time its first byte came, counted from `start`.
+async fn read_response(conn: &mut TcpConnection, until: ReadUntil, max: usize, start: Instant, deadline: Instant) -> (Vec<u8>, Option<Duration>, Result<(), ReadError>) {
+    let mut first = (!conn.buffer.is_empty()).then(|| start.elapsed());
+    let mut chunk = [0u8; 8192];
+    loop {
+        let error = match until.complete(&conn.buffer, max) {
+            Ok(Some(len)) => {
+                let rest = conn.buffer.split_off(len);
+                return (std::mem::replace(&mut conn.buffer, rest), first, Ok(()));
+            },
+            Err(error) => error,
+            Ok(None) => match tokio::time::timeout_at(deadline.into(), conn.stream.read(&mut chunk)).await {
+                Ok(Ok(0)) => ReadError::Closed,
+                Ok(Ok(n)) => {
+                    first.get_or_insert_with(|| start.elapsed());
+                    conn.buffer.extend_from_slice(&chunk[..n]);
+                    continue;
+                },
+                Ok(Err(e)) => ReadError::Failed(e.to_string()),
+                Err(_) => ReadError::Timeout,
+            },
+        };
+        let read = std::mem::take(&mut conn.buffer);
+        return match (until, error) {
+            (ReadUntil::Timeout, ReadError::Timeout | ReadError::Closed) => (read, first, Ok(())),
+            (_, error) => (read, first, Err(error)),
+        };
+    }
+}
+
+#[async_trait]
+impl Sampler for TcpSampler {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let start_send_timestamp = chrono::Local::now();
+        let addr = format!("{}:{}", ctx.render(&self.host), self.port);
+        let url = format!("tcp://{}", addr);
+        let payload = self.payload.bytes(ctx);
+
+        let start = Instant::now();
+        let reused = if self.reuse_connection {
+            ctx.get_mut::<TcpConnections>().and_then(|conns| conns.0.remove(&addr))
+        } else {
+            None
+        };
+        let mut conn = match reused {
+            Some(conn) => conn,
+            None => match self.open(&addr).await {
+                Ok(stream) => TcpConnection { stream, buffer: Vec::new() },
+                Err(e) => {
+                    let mut re = e.record(start_send_timestamp, &self.label, &url);
+                    re.elapsed(start.elapsed().as_millis() as u64);
+                    return re;
+                },
+            },
+        };
+        let connect = start.elapsed();
+
+        let deadline = Instant::now() + self.timeout;
+        let written = match tokio::time::timeout_at(deadline.into(), conn.stream.write_all(&payload)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(ReadError::Failed(e.to_string())),
+            Err(_) => Err(ReadError::Timeout),
+        };
+        let sent_bytes = if written.is_ok() { payload.len() as u64 } else { 0 };
+        let (response, first, read) = match written {
+            Ok(()) => read_response(&mut conn, self.read_until, self.max_response_size, start, deadline).await,
+            Err(error) => (Vec::new(), None, Err(error)),
+        };
+        let elapsed = start.elapsed();
+        let bytes = response.len() as u64;
+
+        let (code, message, failure, keep) = match read {
+            Ok(()) => (200, "OK".to_string(), None, self.reuse_connection && self.read_until != ReadUntil::Timeout),
+            Err(ReadError::Timeout) => (TIMEOUT_CODE, TimeoutKind::Response.to_string(), Some(TimeoutKind::Response.to_string()), false),
+            Err(ReadError::Closed) => (0, "no data".to_string(), Some("connection closed before the end of the response".to_string()), false),
+            Err(ReadError::Failed(e)) => (0, "no data".to_string(), Some(e), false),
+        };
+        if keep {
+            if ctx.get::<TcpConnections>().is_none() {
+                ctx.insert(TcpConnections::default());
+            }
+            if let Some(conns) = ctx.get_mut::<TcpConnections>() {
+                conns.0.insert(addr, conn);
+            }
+        }
+
+        let data_type = if std::str::from_utf8(&response).is_ok() { "text" } else { "bin" };
+        let mut re = RecordData::new(
+            start_send_timestamp.timestamp_millis() as u128,
+            elapsed.as_millis() as u64,
+            self.label.clone(),
+            code,
+            message,
+            "".to_string(),
+            data_type.to_string(),
+            failure.is_none(),
+            failure,
+            bytes,
+            sent_bytes,
+            0,
+            0,
+            url,
+            first.unwrap_or(elapsed).as_millis() as u64,
+            0,
+            connect.as_millis() as u64,
+            Some(ResponseResult::from_bytes(HashMap::new(), response)),
+        );
+        re.body_size(bytes);
+        re.decoded_body_size(bytes);
+        extractors::apply(&self.extractors, &re, ctx);
+        assertions::apply(&self.assertions, &mut re);
+        re
+    }
+}
+
+#[cfg(test)]
+mod tcp_tests {
+    use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}}, time::Duration};
+
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    use crate::{context::VUContext, Sampler, record::TIMEOUT_CODE, samplers::test_server::spawn_server};
+
+    use super::{Payload, ReadUntil, TcpSampler};
+
+    /// A server echoing what it gets, counting its connections.
+    async fn echo_server() -> (u16, Arc<AtomicUsize>) {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let count = connections.clone();
+        let addr = spawn_server(move |mut socket| {
+            count.fetch_add(1, Ordering::Relaxed);
+            async move {
+                let mut buf = [0u8; 1024];
+                while let Ok(n @ 1..) = socket.read(&mut buf).await {
+                    socket.write_all(&buf[..n]).await.unwrap();
+                }
+            }
+        }).await;
+        (addr.port(), connections)
+    }
+
+    #[tokio::test]
+    async fn echo() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let (port, connections) = echo_server().await;
+        let tcp = |payload: Payload| TcpSampler::new("tcp", "127.0.0.1", port, payload).reuse_connection(true);
+
+        ctx.vars_mut().set("name", "rumeter");
+        let re = tcp(Payload::Template("hello ${name}\n".to_string())).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.response_result().unwrap().response_data(), "hello rumeter\n");
+        assert_eq!((re.get_bytes(), re.get_sent_bytes()), (14, 14));
+        assert_eq!(re.get_url(), format!("tcp://127.0.0.1:{}", port));
+        assert!(re.get_latency() >= re.get_connect() && re.get_elapsed() >= re.get_latency());
+
+        let re = tcp(Payload::hex("00 03 61 62 63 ff").unwrap())
+            .read_until(ReadUntil::LengthPrefix { size: 2, big_endian: true })
+            .run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_bytes(), b"\x00\x03abc");
+        // the rest of the echo is the start of the next response
+        let re = tcp(Payload::Text("abc".to_string())).read_until(ReadUntil::Length(2)).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_bytes(), b"\xffa");
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+        let re = tcp(Payload::Text("no end".to_string())).read_until(ReadUntil::Timeout).timeout(Duration::from_millis(100)).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.response_result().unwrap().response_data(), "bcno end");
+
+        let re = tcp(Payload::Text("no end".to_string())).timeout(Duration::from_millis(100)).run(&mut ctx).await;
+        assert_eq!((re.is_success(), re.get_response_code()), (false, TIMEOUT_CODE));
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let re = TcpSampler::new("tcp", "127.0.0.1", closed, Payload::Text("\n".to_string())).run(&mut ctx).await;
+        assert!(!re.is_success());
+        assert_eq!(re.get_sent_bytes(), 0);
+    }
+    #[tokio::test]
+    async fn limit_response_size() {
+        let overflow = ReadUntil::LengthPrefix { size: 16, big_endian: true };
+        assert!(overflow.complete(&[0xff; 16], usize::MAX).is_err());
+        assert_eq!(ReadUntil::LengthPrefix { size: 2, big_endian: false }.complete(b"\x03\x00abc", 5).ok(), Some(Some(5)));
+
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let (port, _) = echo_server().await;
+        let tcp = |payload: &str| TcpSampler::new("tcp", "127.0.0.1", port, Payload::Text(payload.to_string())).max_response_size(8);
+        assert!(tcp("hello\n").run(&mut ctx).await.is_success());
+        let re = tcp("hello world\n").run(&mut ctx).await;
+        assert!(!re.is_success());
+        assert_eq!(re.get_failure_message(), Some("response larger than 8 bytes"));
+        let re = tcp("\x00\x20").read_until(ReadUntil::LengthPrefix { size: 2, big_endian: true }).run(&mut ctx).await;
+        assert_eq!(re.get_failure_message(), Some("response larger than 8 bytes"));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{future::Future, net::SocketAddr, sync::Arc};
+
+use rustls::{pki_types::PrivateKeyDer, server::WebPkiClientVerifier, RootCertStore, ServerConfig, SupportedProtocolVersion};
+use tokio::{io::{AsyncRead, AsyncReadExt}, net::{TcpListener, TcpStream}};
+use tokio_tungstenite::{tungstenite::handshake::server::{Request, Response}, WebSocketStream};
+
+/// Listen on a free port of 127.0.0.1 and run `serve` on every connection it
+/// accepts, each in its own task.
+pub(crate) async fn spawn_server<F, Fut>(serve: F) -> SocketAddr
+where
+    F: Fn(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(serve(socket));
+        }
+    });
+    addr
+}
+
+/// A self-signed certificate for `name`.
+pub(crate) fn self_signed(name: &str) -> rcgen::CertifiedKey {
+    rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap()
+}
+
+/// Server side TLS with `cert` for `versions`, asking for a client certificate
+/// signed by `client_ca` when there is one.
+pub(crate) fn tls_server_config(cert: &rcgen::CertifiedKey, versions: &[&'static SupportedProtocolVersion], client_ca: Option<&rcgen::CertifiedKey>) -> ServerConfig {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions).unwrap();
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            roots.add(ca.cert.der().clone()).unwrap();
+            builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap())
+        },
+        None => builder.with_no_client_auth(),
+    };
+    let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+    builder.with_single_cert(vec![cert.cert.der().clone()], key).unwrap()
+}
+
+/// Accept a websocket on `socket`, agreeing to the subprotocol the client asks for.
+pub(crate) async fn accept_ws(socket: TcpStream) -> WebSocketStream<TcpStream> {
+    // the callback type is the one of tungstenite
+    #[allow(clippy::result_large_err)]
+    let pick_protocol = |req: &Request, mut resp: Response| {
+        if let Some(protocol) = req.headers().get("sec-websocket-protocol") {
+            resp.headers_mut().insert("sec-websocket-protocol", protocol.clone());
+        }
+        Ok(resp)
+    };
+    tokio_tungstenite::accept_hdr_async(socket, pick_protocol).await.unwrap()
+}
+
+/// A request read by [`read_request`].
+pub(crate) struct ReceivedRequest {
+    /// Request line and headers, without the empty line closing them.
+    pub(crate) head: String,
+    pub(crate) body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    pub(crate) fn line(&self) -> &str {
+        self.head.lines().next().unwrap_or("")
+    }
+
+    pub(crate) fn method(&self) -> &str {
+        self.line().split(' ').next().unwrap_or("")
+    }
+
+    pub(crate) fn target(&self) -> &str {
+        self.line().split(' ').nth(1).unwrap_or("")
+    }
+
+    pub(crate) fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Bytes the request took on the connection.
+    pub(crate) fn size(&self) -> usize {
+        self.head.len() + 4 + self.body.len()
+    }
+}
+
+/// Read one request with a `Content-Length` body or none, leaving what follows
+/// on the connection. None when the connection closes first.
+pub(crate) async fn read_request<S: AsyncRead + Unpin>(io: &mut S) -> Option<ReceivedRequest> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(io.read_u8().await.ok()?);
+    }
+    head.truncate(head.len() - 4);
+    let head = String::from_utf8_lossy(&head).into_owned();
+    let length = head.lines()
+        .filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    io.read_exact(&mut body).await.ok()?;
+    Some(ReceivedRequest { head, body })
+}
@@ -0,0 +1,457 @@
+use std::{error::Error, fmt, fs, io::BufReader, path::{Path, PathBuf}, sync::{Arc, OnceLock}};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{cipher::{AeadKey, Iv, KeyBlockShape, MessageDecrypter, MessageEncrypter, Tls12AeadAlgorithm, Tls13AeadAlgorithm, UnsupportedOperationError}, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CipherSuiteCommon, ConnectionTrafficSecrets, DigitallySignedStruct, RootCertStore, SignatureScheme, SupportedCipherSuite,
+    Tls12CipherSuite, Tls13CipherSuite,
+};
+use tracing::*;
+
+use super::client;
+
+pub use crate::record::TlsSession;
+
+/// Lowest TLS version a client accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+#[derive(Debug, Clone)]
+enum Identity {
+    Pem { cert: PathBuf, key: PathBuf },
+    Pkcs12 { path: PathBuf, password: String },
+}
+
+/// TLS options of the clients, given to [`super::client::ClientConfig::tls`]
+/// once built. Files are read by [`TlsConfig::build`].
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    native_roots: bool,
+    ca_bundles: Vec<PathBuf>,
+    identity: Option<Identity>,
+    accept_invalid_certs: bool,
+    min_version: TlsVersion,
+    sni: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            native_roots: true,
+            ca_bundles: Vec::new(),
+            identity: None,
+            accept_invalid_certs: false,
+            min_version: TlsVersion::default(),
+            sni: true,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the certificates of the system, on by default.
+    pub fn native_roots(mut self, native_roots: bool) -> Self {
+        self.native_roots = native_roots;
+        self
+    }
+
+    /// Trust the certificates of a PEM file.
+    pub fn ca_bundle(mut self, path: impl AsRef<Path>) -> Self {
+        self.ca_bundles.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Authenticate with a PEM certificate chain and its private key, which may
+    /// be in the same file.
+    pub fn client_cert_pem(mut self, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Self {
+        self.identity = Some(Identity::Pem { cert: cert.as_ref().to_path_buf(), key: key.as_ref().to_path_buf() });
+        self
+    }
+
+    /// Authenticate with the first key and certificate chain of a PKCS#12 file.
+    pub fn client_pkcs12(mut self, path: impl AsRef<Path>, password: &str) -> Self {
+        self.identity = Some(Identity::Pkcs12 { path: path.as_ref().to_path_buf(), password: password.to_string() });
+        self
+    }
+
+    /// Accept any server certificate, expired, self-signed or for another host.
+    /// Only for test environments.
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    pub fn min_version(mut self, min_version: TlsVersion) -> Self {
+        self.min_version = min_version;
+        self
+    }
+
+    /// Send the host name in the handshake, on by default.
+    pub fn sni(mut self, sni: bool) -> Self {
+        self.sni = sni;
+        self
+    }
+
+    pub fn build(&self) -> Result<TlsContext, Box<dyn Error>> {
+        let provider = Arc::new(recording_provider());
+        let versions: &[&rustls::SupportedProtocolVersion] = match self.min_version {
+            TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+            TlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+        let builder = if self.accept_invalid_certs {
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(AnyServerCert(provider)))
+        } else {
+            let mut roots = RootCertStore::empty();
+            if self.native_roots {
+                let native = rustls_native_certs::load_native_certs();
+                for e in native.errors {
+                    warn!("cannot load system certificates: {}", e);
+                }
+                roots.add_parsable_certificates(native.certs);
+            }
+            for path in &self.ca_bundles {
+                for cert in pem_certs(path)? {
+                    roots.add(cert)?;
+                }
+            }
+            builder.with_root_certificates(roots)
+        };
+        let mut config = match &self.identity {
+            Some(identity) => {
+                let (chain, key) = identity.load()?;
+                builder.with_client_auth_cert(chain, key)?
+            },
+            None => builder.with_no_client_auth(),
+        };
+        config.enable_sni = self.sni;
+        Ok(TlsContext { config: Arc::new(config) })
+    }
+}
+
+impl Identity {
+    fn load(&self) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Box<dyn Error>> {
+        match self {
+            Identity::Pem { cert, key } => {
+                let chain = pem_certs(cert)?;
+                let key = rustls_pemfile::private_key(&mut BufReader::new(&read(key)?[..]))?
+                    .ok_or_else(|| format!("no private key in {}", key.display()))?;
+                Ok((chain, key))
+            },
+            Identity::Pkcs12 { path, password } => {
+                let store = p12_keystore::KeyStore::from_pkcs12(&read(path)?, password)
+                    .map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+                let (_, entry) = store.private_key_chain()
+                    .ok_or_else(|| format!("no private key in {}", path.display()))?;
+                let chain = entry.chain().iter().map(|c| CertificateDer::from(c.as_der().to_vec())).collect();
+                let key = PrivateKeyDer::try_from(entry.key().to_vec())?;
+                Ok((chain, key))
+            },
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e).into())
+}
+
+fn pem_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(&read(path)?[..])).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", path.display()).into());
+    }
+    Ok(certs)
+}
+
+/// A built [`TlsConfig`], cheap to clone.
+#[derive(Clone)]
+pub struct TlsContext {
+    config: Arc<rustls::ClientConfig>,
+}
+
+impl Default for TlsContext {
+    /// The system certificates, TLS 1.2 and up.
+    fn default() -> Self {
+        static DEFAULT: OnceLock<TlsContext> = OnceLock::new();
+        DEFAULT.get_or_init(|| TlsConfig::new().build().expect("cannot build default tls config")).clone()
+    }
+}
+
+impl fmt::Debug for TlsContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsContext").finish_non_exhaustive()
+    }
+}
+
+impl TlsContext {
+    /// The rustls config of a new client offering the `alpn` protocols. Each
+    /// client resumes its own sessions only.
+    pub(crate) fn client_config(&self, alpn: &[&[u8]]) -> rustls::ClientConfig {
+        let mut config = self.config.as_ref().clone();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        config.resumption = rustls::client::Resumption::in_memory_sessions(256);
+        config
+    }
+}
+
+impl TlsSession {
+    /// What a connection negotiated, once its handshake is done.
+    pub(crate) fn of(conn: &rustls::ClientConnection) -> Option<TlsSession> {
+        let protocol = match conn.protocol_version()? {
+            rustls::ProtocolVersion::TLSv1_3 => "TLSv1.3",
+            _ => "TLSv1.2",
+        };
+        let cipher = conn.negotiated_cipher_suite()?.suite().as_str().unwrap_or("unknown");
+        Some(TlsSession { protocol, cipher })
+    }
+}
+
+/// The ring provider, with cipher suites telling the connector which one
+/// a handshake picked: rustls keeps the negotiated suite inside the connection,
+/// which reqwest does not hand out, so [`TlsSession::of`] cannot be used.
+///
+/// The suite goes through a task-local of [`client`] to the connector layer,
+/// which keeps it for the local address of the connection. A handshake hyper
+/// finishes in a background task, after the request took a pooled connection
+/// instead, reports nothing.
+fn recording_provider() -> CryptoProvider {
+    static SUITES: OnceLock<Vec<SupportedCipherSuite>> = OnceLock::new();
+    let mut provider = rustls::crypto::ring::default_provider();
+    provider.cipher_suites = SUITES.get_or_init(|| provider.cipher_suites.iter().map(|s| recording_suite(*s)).collect()).clone();
+    provider
+}
+
+fn recording_suite(suite: SupportedCipherSuite) -> SupportedCipherSuite {
+    let cipher = suite.suite().as_str().unwrap_or("unknown");
+    let common = |c: &CipherSuiteCommon| CipherSuiteCommon {
+        suite: c.suite,
+        hash_provider: c.hash_provider,
+        confidentiality_limit: c.confidentiality_limit,
+    };
+    // built once per process, see recording_provider
+    match suite {
+        SupportedCipherSuite::Tls13(s) => SupportedCipherSuite::Tls13(Box::leak(Box::new(Tls13CipherSuite {
+            common: common(&s.common),
+            hkdf_provider: s.hkdf_provider,
+            aead_alg: Box::leak(Box::new(Recording { inner: s.aead_alg, session: TlsSession { protocol: "TLSv1.3", cipher } })),
+            quic: s.quic,
+        }))),
+        SupportedCipherSuite::Tls12(s) => SupportedCipherSuite::Tls12(Box::leak(Box::new(Tls12CipherSuite {
+            common: common(&s.common),
+            prf_provider: s.prf_provider,
+            kx: s.kx,
+            sign: s.sign,
+            aead_alg: Box::leak(Box::new(Recording { inner: s.aead_alg, session: TlsSession { protocol: "TLSv1.2", cipher } })),
+        }))),
+    }
+}
+
+/// An aead algorithm reporting its suite when a connection starts encrypting with it.
+struct Recording<A: ?Sized + 'static> {
+    inner: &'static A,
+    session: TlsSession,
+}
+
+impl Tls13AeadAlgorithm for Recording<dyn Tls13AeadAlgorithm> {
+    fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> {
+        client::negotiated(self.session);
+        self.inner.encrypter(key, iv)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> {
+        self.inner.decrypter(key, iv)
+    }
+
+    fn key_len(&self) -> usize {
+        self.inner.key_len()
+    }
+
+    fn extract_keys(&self, key: AeadKey, iv: Iv) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.inner.extract_keys(key, iv)
+    }
+
+    fn fips(&self) -> bool {
+        self.inner.fips()
+    }
+}
+
+impl Tls12AeadAlgorithm for Recording<dyn Tls12AeadAlgorithm> {
+    fn encrypter(&self, key: AeadKey, iv: &[u8], extra: &[u8]) -> Box<dyn MessageEncrypter> {
+        client::negotiated(self.session);
+        self.inner.encrypter(key, iv, extra)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        self.inner.decrypter(key, iv)
+    }
+
+    fn key_block_shape(&self) -> KeyBlockShape {
+        self.inner.key_block_shape()
+    }
+
+    fn extract_keys(&self, key: AeadKey, iv: &[u8], explicit: &[u8]) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.inner.extract_keys(key, iv, explicit)
+    }
+
+    fn fips(&self) -> bool {
+        self.inner.fips()
+    }
+}
+
+/// Verifier of [`TlsConfig::accept_invalid_certs`]: any certificate goes, the
+/// handshake signatures are still checked.
+#[derive(Debug)]
+struct AnyServerCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use tempfile::TempDir;
+
+    use rustls::{ServerConfig, version::{TLS12, TLS13}};
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{context::VUContext, record::RecordData, Sampler, samplers::{client::{ClientConfig, ClientPool, PoolScope}, http::{HttpSampler, Method}, test_server::{read_request, self_signed, spawn_server, tls_server_config}}};
+
+    use super::{TlsConfig, TlsVersion};
+
+    /// An https server on `localhost` answering `ok` to every request.
+    async fn tls_server(config: ServerConfig) -> String {
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let addr = spawn_server(move |socket| {
+            let acceptor = acceptor.clone();
+            async move {
+                let Ok(mut stream) = acceptor.accept(socket).await else { return };
+                while read_request(&mut stream).await.is_some() {
+                    if stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.is_err() {
+                        return;
+                    }
+                    _ = stream.flush().await;
+                }
+            }
+        }).await;
+        format!("https://localhost:{}/", addr.port())
+    }
+
+    fn write(dir: &TempDir, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Two requests of one virtual user, the second on the pooled connection.
+    async fn get_twice(tls: &TlsConfig, url: &str) -> [RecordData; 2] {
+        let client = ClientConfig::default().tls(tls.build().unwrap()).build();
+        let mut ctx = VUContext::with_client(1, "Thread Group 1-1", client, None, Default::default());
+        let sampler = HttpSampler::new("tls", url, Method::GET, Default::default(), None);
+        [sampler.run(&mut ctx).await, sampler.run(&mut ctx).await]
+    }
+
+    #[tokio::test]
+    async fn verify_server_cert() {
+        let server = self_signed("localhost");
+        let dir = tempfile::tempdir().unwrap();
+        let ca = write(&dir, "rumeter_tls_server.pem", server.cert.pem());
+        let url = tls_server(tls_server_config(&server, &[&TLS13, &TLS12], None)).await;
+
+        let [re, _] = get_twice(&TlsConfig::new().native_roots(false), &url).await;
+        assert!(!re.is_success());
+        assert_eq!((re.get_response_code(), re.get_tls()), (0, None));
+
+        for re in get_twice(&TlsConfig::new().ca_bundle(&ca), &url).await {
+            assert!(re.is_success());
+            let tls = re.get_tls().unwrap();
+            assert_eq!(tls.get_protocol(), "TLSv1.3");
+            assert!(tls.get_cipher().starts_with("TLS13_"));
+        }
+
+        let [re, _] = get_twice(&TlsConfig::new().native_roots(false).accept_invalid_certs(true), &url).await;
+        assert!(re.is_success());
+
+        let url = tls_server(tls_server_config(&server, &[&TLS12], None)).await;
+        let [re, _] = get_twice(&TlsConfig::new().ca_bundle(&ca), &url).await;
+        assert_eq!(re.get_tls().unwrap().get_protocol(), "TLSv1.2");
+        assert!(re.get_tls().unwrap().get_cipher().starts_with("TLS_ECDHE_"));
+        let [re, _] = get_twice(&TlsConfig::new().ca_bundle(&ca).min_version(TlsVersion::Tls13), &url).await;
+        assert_eq!((re.is_success(), re.get_tls()), (false, None));
+
+        let err = TlsConfig::new().ca_bundle("/no/such/ca.pem").build().unwrap_err();
+        assert!(err.to_string().starts_with("cannot read /no/such/ca.pem"));
+    }
+
+    #[tokio::test]
+    async fn pooled_sessions() {
+        let server = self_signed("localhost");
+        let url = tls_server(tls_server_config(&server, &[&TLS12], None)).await;
+        let tls = TlsConfig::new().native_roots(false).accept_invalid_certs(true).build().unwrap();
+        let pool = ClientPool::new(ClientConfig::default().scope(PoolScope::Group).tls(tls));
+        let sampler = HttpSampler::new("tls", &url, Method::GET, Default::default(), None);
+
+        let mut first = VUContext::with_client(1, "Thread Group 1-1", pool.client(), None, Default::default());
+        let opened = sampler.run(&mut first).await;
+        // the second virtual user gets the connection the first one left in the pool
+        let mut second = VUContext::with_client(2, "Thread Group 1-2", pool.client(), None, Default::default());
+        let pooled = sampler.run(&mut second).await;
+        assert_eq!(opened.get_tls().unwrap().get_protocol(), "TLSv1.2");
+        assert_eq!(pooled.get_tls().unwrap().get_cipher(), opened.get_tls().unwrap().get_cipher());
+    }
+
+    #[tokio::test]
+    async fn client_certificates() {
+        let server = self_signed("localhost");
+        let client = self_signed("rumeter");
+        let dir = tempfile::tempdir().unwrap();
+        let ca = write(&dir, "rumeter_mtls_server.pem", server.cert.pem());
+        let url = tls_server(tls_server_config(&server, &[&TLS13, &TLS12], Some(&client))).await;
+
+        let [re, _] = get_twice(&TlsConfig::new().ca_bundle(&ca), &url).await;
+        assert!(!re.is_success());
+
+        let cert = write(&dir, "rumeter_mtls_client.pem", client.cert.pem());
+        let key = write(&dir, "rumeter_mtls_client.key", client.key_pair.serialize_pem());
+        let [re, _] = get_twice(&TlsConfig::new().ca_bundle(&ca).client_cert_pem(&cert, &key), &url).await;
+        assert!(re.is_success());
+
+        let mut store = p12_keystore::KeyStore::new();
+        let chain = p12_keystore::PrivateKeyChain::new(client.key_pair.serialize_der(), [1], [p12_keystore::Certificate::from_der(client.cert.der()).unwrap()]);
+        store.add_entry("rumeter", p12_keystore::KeyStoreEntry::PrivateKeyChain(chain));
+        let p12 = write(&dir, "rumeter_mtls_client.p12", store.writer("secret").write().unwrap());
+        let [re, _] = get_twice(&TlsConfig::new().ca_bundle(&ca).client_pkcs12(&p12, "secret"), &url).await;
+        assert!(re.is_success());
+        assert!(TlsConfig::new().client_pkcs12(&p12, "wrong").build().is_err());
+    }
+}
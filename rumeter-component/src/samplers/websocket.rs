@@ -0,0 +1,662 @@
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use tokio_tungstenite::tungstenite::{Message, protocol::{CloseFrame, frame::coding::CloseCode}};
+use crate::{Sampler, Assertion, Extractor, record::{RecordData, ResponseResult, TIMEOUT_CODE}, assertions::{self, AssertionRef, TextPattern}, extractors::{self, ExtractorRef}, context::VUContext};
+
+use super::{http::{HeaderMap, render_headers}, client::TimeoutKind, exchange, proxy::ProxyConfig, ws::{self, WsConnection}};
+
+/// The connection of a virtual user, in its context.
+struct WebSocketState {
+    conn: WsConnection,
+    url: String,
+    /// Messages received while waiting for something else, read first, with
+    /// the bytes of their frames.
+    pending: VecDeque<(Message, u64)>,
+}
+
+impl WebSocketState {
+    /// Keep a message for a later read, the bytes of its frame being counted
+    /// by the step reading it rather than by `step`.
+    fn set_aside(&mut self, step: &mut Step, msg: Message) {
+        let size = frame_size(&msg);
+        step.received += size;
+        self.pending.push_back((msg, size));
+    }
+}
+
+/// Bytes of the frame of a message from the server, which is not masked.
+fn frame_size(msg: &Message) -> u64 {
+    let len = msg.len() as u64;
+    let extended = match len {
+        0..=125 => 0,
+        126..=0xffff => 2,
+        _ => 8,
+    };
+    2 + extended + len
+}
+
+/// The record of a sampler run by a virtual user without a websocket.
+fn not_connected(label: &str) -> RecordData {
+    exchange::failed_record(chrono::Local::now(), label, "", "no websocket connection, see WebSocketOpenSampler".to_string(), 0)
+}
+
+/// Why a websocket step failed.
+enum StepError {
+    Timeout(TimeoutKind),
+    Failed(String),
+}
+
+/// What a websocket step measured.
+struct Step {
+    start_send_timestamp: chrono::DateTime<chrono::Local>,
+    start: Instant,
+    sent: u64,
+    received: u64,
+}
+
+impl Step {
+    fn start(state: &WebSocketState) -> Self {
+        Self {
+            start_send_timestamp: chrono::Local::now(),
+            start: Instant::now(),
+            sent: state.conn.traffic.sent(),
+            received: state.conn.traffic.received(),
+        }
+    }
+
+    /// The record of the step, the bytes being what went through the
+    /// connection since it started.
+    fn record(&self, state: &WebSocketState, label: &str, latency: Duration, failure: Option<StepError>, message: Option<&Message>) -> RecordData {
+        let (code, response_message, failure) = match failure {
+            Some(StepError::Timeout(kind)) => (TIMEOUT_CODE, kind.to_string(), Some(kind.to_string())),
+            Some(StepError::Failed(reason)) => (state.conn.response_code, state.conn.response_message.clone(), Some(reason)),
+            None => (state.conn.response_code, state.conn.response_message.clone(), None),
+        };
+        let (data_type, result) = match message {
+            Some(Message::Binary(bytes)) => ("bin", Some(ResponseResult::from_bytes(HashMap::new(), bytes.clone()))),
+            Some(Message::Text(text)) => ("text", Some(ResponseResult::new(HashMap::new(), text.clone()))),
+            _ => ("text", None),
+        };
+        let size = message.map_or(0, |m| m.len() as u64);
+        let mut re = RecordData::new(
+            self.start_send_timestamp.timestamp_millis() as u128,
+            self.start.elapsed().as_millis() as u64,
+            label.to_string(),
+            code,
+            response_message,
+            "".to_string(),
+            data_type.to_string(),
+            failure.is_none(),
+            failure,
+            state.conn.traffic.received().saturating_sub(self.received),
+            state.conn.traffic.sent() - self.sent,
+            0,
+            0,
+            state.url.clone(),
+            latency.as_millis() as u64,
+            0,
+            0,
+            result,
+        );
+        re.body_size(size);
+        re.decoded_body_size(size);
+        re
+    }
+}
+
+/// Open the websocket of the virtual user, closing the one it had. The
+/// response is the one of the upgrade request.
+#[derive(Clone)]
+pub struct WebSocketOpenSampler {
+    label: String,
+    url: String,
+    headers: HeaderMap,
+    protocols: Vec<String>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<ProxyConfig>,
+    assertions: Vec<AssertionRef>,
+    extractors: Vec<ExtractorRef>,
+}
+
+impl WebSocketOpenSampler {
+    /// `url` is a `ws://` or `wss://` url, `http` and `https` are turned into them.
+    pub fn new(label: &str, url: &str, headers: HeaderMap) -> Self {
+        Self {
+            label: label.to_string(),
+            url: url.to_string(),
+            headers,
+            protocols: Vec::new(),
+            connect_timeout: None,
+            proxy: None,
+            assertions: Vec::new(),
+            extractors: Vec::new(),
+        }
+    }
+
+    /// Ask for a sub-protocol, the server picks one of the ones asked.
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.protocols.push(protocol.to_string());
+        self
+    }
+
+    /// Time to open the connection, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Proxy of this sampler, over the one of the [`crate::config::defaults::HttpDefaults`].
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn assertion(mut self, assertion: impl Assertion + 'static) -> Self {
+        self.assertions.push(Arc::new(assertion));
+        self
+    }
+
+    pub fn extractor(mut self, extractor: impl Extractor + 'static) -> Self {
+        self.extractors.push(Arc::new(extractor));
+        self
+    }
+}
+
+#[async_trait]
+impl Sampler for WebSocketOpenSampler {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        if let Some(mut old) = ctx.remove::<WebSocketState>() {
+            _ = old.conn.stream.close(None).await;
+        }
+        let start_send_timestamp = chrono::Local::now();
+        let defaults = ctx.http_defaults();
+        let url = ctx.render(&self.url);
+        let url = ws::ws_url(&defaults.url(&url, ctx));
+        let headers = render_headers(&self.headers, ctx);
+        let protocols: Vec<&str> = self.protocols.iter().map(String::as_str).collect();
+        let connect_timeout = self.connect_timeout.or(defaults.get_timeouts().get_connect());
+
+        let start = Instant::now();
+        let mut re = match ws::connect(ctx, &url, headers, &protocols, self.proxy.as_ref().or(defaults.get_proxy()), connect_timeout).await {
+            Ok(conn) => {
+                let elapsed = start.elapsed();
+                let mut re = RecordData::new(
+                    start_send_timestamp.timestamp_millis() as u128,
+                    elapsed.as_millis() as u64,
+                    self.label.clone(),
+                    conn.response_code,
+                    conn.response_message.clone(),
+                    "".to_string(),
+                    "text".to_string(),
+                    true,
+                    None,
+                    conn.traffic.received(),
+                    conn.traffic.sent(),
+                    0,
+                    0,
+                    url.clone(),
+                    elapsed.as_millis() as u64,
+                    0,
+                    conn.connect.as_millis() as u64,
+                    Some(ResponseResult::new(conn.response_headers.clone(), String::new())),
+                );
+                re.tls(conn.tls);
+                ctx.insert(WebSocketState { conn, url, pending: VecDeque::new() });
+                re
+            },
+            Err(e) => {
+                let mut re = e.record(start_send_timestamp, &self.label, &url);
+                re.elapsed(start.elapsed().as_millis() as u64);
+                re
+            },
+        };
+        extractors::apply(&self.extractors, &re, ctx);
+        assertions::apply(&self.assertions, &mut re);
+        re
+    }
+}
+
+/// A frame sent by a [`WebSocketWriteSampler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A text frame, rendered as a `${...}` template.
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Send one frame on the websocket of the virtual user.
+#[derive(Clone)]
+pub struct WebSocketWriteSampler {
+    label: String,
+    frame: Frame,
+}
+
+impl WebSocketWriteSampler {
+    pub fn new(label: &str, frame: Frame) -> Self {
+        Self { label: label.to_string(), frame }
+    }
+}
+
+#[async_trait]
+impl Sampler for WebSocketWriteSampler {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let msg = match &self.frame {
+            Frame::Text(text) => Message::Text(ctx.render(text)),
+            Frame::Binary(bytes) => Message::Binary(bytes.clone()),
+        };
+        let mut state = match ctx.remove::<WebSocketState>() {
+            Some(state) => state,
+            None => return not_connected(&self.label),
+        };
+        let step = Step::start(&state);
+        let failure = state.conn.stream.send(msg).await.err().map(|e| StepError::Failed(e.to_string()));
+        let re = step.record(&state, &self.label, step.start.elapsed(), failure, None);
+        if re.is_success() {
+            ctx.insert(state);
+        }
+        re
+    }
+}
+
+/// Which message a [`WebSocketReadSampler`] waits for.
+#[derive(Clone, Debug, Default)]
+pub enum MessageFilter {
+    /// The next message.
+    #[default]
+    Any,
+    /// A text message matching the pattern.
+    Text(TextPattern),
+    /// A json message with a value at the path, equal to the expected one if set.
+    JsonPath(JsonPath, Option<Value>),
+}
+
+impl MessageFilter {
+    pub fn json_path(path: &str, expected: Option<Value>) -> Result<Self, serde_json_path::ParseError> {
+        Ok(MessageFilter::JsonPath(JsonPath::parse(path)?, expected))
+    }
+
+    fn test(&self, msg: &Message) -> bool {
+        let text = match msg {
+            Message::Text(text) => text.as_str(),
+            Message::Binary(bytes) => std::str::from_utf8(bytes).unwrap_or(""),
+            _ => return false,
+        };
+        match self {
+            MessageFilter::Any => true,
+            MessageFilter::Text(pattern) => pattern.test(text),
+            MessageFilter::JsonPath(path, expected) => {
+                let json: Value = match serde_json::from_str(text) {
+                    Ok(json) => json,
+                    Err(_) => return false,
+                };
+                match (path.query(&json).first(), expected) {
+                    (Some(value), Some(expected)) => value == expected,
+                    (found, None) => found.is_some(),
+                    (None, Some(_)) => false,
+                }
+            },
+        }
+    }
+}
+
+/// Wait for a message on the websocket of the virtual user, the ones not
+/// matching its filter being kept for the next reads. The message is the
+/// response data, the latency the time to the first message received, or 0
+/// for a message received before the read.
+#[derive(Clone)]
+pub struct WebSocketReadSampler {
+    label: String,
+    filter: MessageFilter,
+    timeout: Duration,
+    assertions: Vec<AssertionRef>,
+    extractors: Vec<ExtractorRef>,
+}
+
+impl WebSocketReadSampler {
+    /// Fail when no message matches within `timeout`.
+    pub fn new(label: &str, timeout: Duration) -> Self {
+        Self { label: label.to_string(), filter: MessageFilter::Any, timeout, assertions: Vec::new(), extractors: Vec::new() }
+    }
+
+    /// The next message by default.
+    pub fn filter(mut self, filter: MessageFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn assertion(mut self, assertion: impl Assertion + 'static) -> Self {
+        self.assertions.push(Arc::new(assertion));
+        self
+    }
+
+    pub fn extractor(mut self, extractor: impl Extractor + 'static) -> Self {
+        self.extractors.push(Arc::new(extractor));
+        self
+    }
+}
+
+#[async_trait]
+impl Sampler for WebSocketReadSampler {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let mut state = match ctx.remove::<WebSocketState>() {
+            Some(state) => state,
+            None => return not_connected(&self.label),
+        };
+        let mut step = Step::start(&state);
+        let deadline = step.start + self.timeout;
+        let mut first = None;
+        let mut open = true;
+        let kept = state.pending.iter().position(|(msg, _)| self.filter.test(msg)).and_then(|i| state.pending.remove(i));
+        let found = match kept {
+            Some((msg, size)) => {
+                step.received = step.received.saturating_sub(size);
+                first = Some(Duration::ZERO);
+                Ok(msg)
+            },
+            None => loop {
+                let msg = match tokio::time::timeout_at(deadline.into(), state.conn.stream.next()).await {
+                    Ok(Some(Ok(msg @ (Message::Text(_) | Message::Binary(_))))) => msg,
+                    Ok(Some(Ok(Message::Close(_)))) | Ok(None) => {
+                        open = false;
+                        break Err(StepError::Failed("connection closed".to_string()));
+                    },
+                    Ok(Some(Ok(_))) => continue,
+                    Ok(Some(Err(e))) => {
+                        open = false;
+                        break Err(StepError::Failed(e.to_string()));
+                    },
+                    Err(_) => break Err(StepError::Timeout(TimeoutKind::Response)),
+                };
+                first.get_or_insert(step.start.elapsed());
+                if self.filter.test(&msg) {
+                    break Ok(msg);
+                }
+                state.set_aside(&mut step, msg);
+            },
+        };
+        let latency = first.unwrap_or_else(|| step.start.elapsed());
+        let mut re = match found {
+            Ok(msg) => step.record(&state, &self.label, latency, None, Some(&msg)),
+            Err(failure) => step.record(&state, &self.label, latency, Some(failure), None),
+        };
+        if open {
+            ctx.insert(state);
+        }
+        extractors::apply(&self.extractors, &re, ctx);
+        assertions::apply(&self.assertions, &mut re);
+        re
+    }
+}
+
+/// Ping the server and wait for its pong. Messages received meanwhile are
+/// kept for the next [`WebSocketReadSampler`].
+#[derive(Clone)]
+pub struct WebSocketPingSampler {
+    label: String,
+    timeout: Duration,
+}
+
+impl WebSocketPingSampler {
+    /// Fail when no pong comes within `timeout`.
+    pub fn new(label: &str, timeout: Duration) -> Self {
+        Self { label: label.to_string(), timeout }
+    }
+}
+
+#[async_trait]
+impl Sampler for WebSocketPingSampler {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let mut state = match ctx.remove::<WebSocketState>() {
+            Some(state) => state,
+            None => return not_connected(&self.label),
+        };
+        let mut step = Step::start(&state);
+        let deadline = step.start + self.timeout;
+        let payload = format!("rumeter-{}", step.start_send_timestamp.timestamp_millis()).into_bytes();
+        let mut open = true;
+        let failure = match state.conn.stream.send(Message::Ping(payload.clone())).await {
+            Ok(()) => loop {
+                match tokio::time::timeout_at(deadline.into(), state.conn.stream.next()).await {
+                    Ok(Some(Ok(Message::Pong(pong)))) if pong == payload => break None,
+                    Ok(Some(Ok(msg @ (Message::Text(_) | Message::Binary(_))))) => state.set_aside(&mut step, msg),
+                    Ok(Some(Ok(Message::Close(_)))) | Ok(None) => {
+                        open = false;
+                        break Some(StepError::Failed("connection closed".to_string()));
+                    },
+                    Ok(Some(Ok(_))) => {},
+                    Ok(Some(Err(e))) => {
+                        open = false;
+                        break Some(StepError::Failed(e.to_string()));
+                    },
+                    Err(_) => break Some(StepError::Timeout(TimeoutKind::Response)),
+                }
+            },
+            Err(e) => {
+                open = false;
+                Some(StepError::Failed(e.to_string()))
+            },
+        };
+        let re = step.record(&state, &self.label, step.start.elapsed(), failure, None);
+        if open {
+            ctx.insert(state);
+        }
+        re
+    }
+}
+
+/// Close the websocket of the virtual user and wait for the server to close
+/// its side. The response code is the close code of the server.
+#[derive(Clone)]
+pub struct WebSocketCloseSampler {
+    label: String,
+    code: u16,
+    timeout: Duration,
+}
+
+impl WebSocketCloseSampler {
+    /// Close with the code 1000, waiting at most `timeout` for the server.
+    pub fn new(label: &str, timeout: Duration) -> Self {
+        Self { label: label.to_string(), code: 1000, timeout }
+    }
+
+    /// Status code of the close frame.
+    pub fn code(mut self, code: u16) -> Self {
+        self.code = code;
+        self
+    }
+}
+
+#[async_trait]
+impl Sampler for WebSocketCloseSampler {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    async fn run(&self, ctx: &mut VUContext) -> RecordData {
+        let mut state = match ctx.remove::<WebSocketState>() {
+            Some(state) => state,
+            None => return not_connected(&self.label),
+        };
+        let step = Step::start(&state);
+        let deadline = step.start + self.timeout;
+        let close = CloseFrame { code: CloseCode::from(self.code), reason: "".into() };
+        let mut closed_with = None;
+        let failure = match state.conn.stream.close(Some(close)).await {
+            Ok(()) => loop {
+                match tokio::time::timeout_at(deadline.into(), state.conn.stream.next()).await {
+                    Ok(Some(Ok(Message::Close(frame)))) => {
+                        closed_with = frame.map(|f| u16::from(f.code));
+                    },
+                    Ok(Some(Ok(_))) => {},
+                    Ok(None) | Ok(Some(Err(_))) => break None,
+                    Err(_) => break Some(StepError::Timeout(TimeoutKind::Response)),
+                }
+            },
+            Err(e) => Some(StepError::Failed(e.to_string())),
+        };
+        if let Some(code) = closed_with {
+            state.conn.response_code = code;
+            state.conn.response_message = "Close".to_string();
+        }
+        step.record(&state, &self.label, step.start.elapsed(), failure, None)
+    }
+}
+
+#[cfg(test)]
+mod websocket_tests {
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+    use tokio::{io::AsyncWriteExt, net::TcpStream};
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::{context::VUContext, Sampler, record::TIMEOUT_CODE, assertions::TextPattern, config::defaults::HttpDefaults, samplers::{proxy::ProxyConfig, test_server::{accept_ws, read_request, spawn_server}}};
+
+    use super::{Frame, MessageFilter, WebSocketOpenSampler, WebSocketWriteSampler, WebSocketReadSampler, WebSocketPingSampler, WebSocketCloseSampler};
+
+    /// A server echoing the frames it gets, except `burst` answered with
+    /// three json messages.
+    async fn echo_server() -> String {
+        let addr = spawn_server(|socket| async move {
+            let mut ws = accept_ws(socket).await;
+            while let Some(Ok(msg)) = ws.next().await {
+                match msg {
+                    Message::Text(text) if text == "burst" => {
+                        for n in 0..3 {
+                            ws.send(Message::Text(json!({"n": n}).to_string())).await.unwrap();
+                        }
+                    },
+                    msg @ (Message::Text(_) | Message::Binary(_)) => ws.send(msg).await.unwrap(),
+                    _ => {},
+                }
+            }
+        }).await;
+        format!("ws://{}/echo", addr)
+    }
+
+    /// A http proxy opening the tunnels it is asked for, and the heads of the
+    /// CONNECT requests it got.
+    async fn tunnel_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+        let connects = Arc::new(Mutex::new(Vec::new()));
+        let seen = connects.clone();
+        let addr = spawn_server(move |mut socket| {
+            let seen = seen.clone();
+            async move {
+                let req = read_request(&mut socket).await.unwrap();
+                let mut upstream = TcpStream::connect(req.target()).await.unwrap();
+                seen.lock().unwrap().push(req.head);
+                socket.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await.unwrap();
+                _ = tokio::io::copy_bidirectional(&mut socket, &mut upstream).await;
+            }
+        }).await;
+        (format!("http://{}", addr), connects)
+    }
+
+    #[tokio::test]
+    async fn through_proxy() {
+        let url = echo_server().await;
+        let (proxy_url, connects) = tunnel_proxy().await;
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        ctx.set_http_defaults(HttpDefaults::new().proxy(ProxyConfig::new(&proxy_url).unwrap().auth("user", "pass")));
+
+        let re = WebSocketOpenSampler::new("open", &url, Default::default()).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        WebSocketWriteSampler::new("write", Frame::Text("hello".to_string())).run(&mut ctx).await;
+        let re = WebSocketReadSampler::new("read", Duration::from_secs(1)).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_data(), "hello");
+        let authority = url.trim_start_matches("ws://").trim_end_matches("/echo");
+        assert_eq!(
+            connects.lock().unwrap().as_slice(),
+            [format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\nProxy-Authorization: Basic dXNlcjpwYXNz", authority)],
+        );
+
+        let bypassed = ProxyConfig::new(&proxy_url).unwrap().no_proxy("127.0.0.1");
+        let re = WebSocketOpenSampler::new("open", &url, Default::default()).proxy(bypassed).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(connects.lock().unwrap().len(), 1);
+
+        let socks = ProxyConfig::new("socks5://127.0.0.1:1080").unwrap();
+        let re = WebSocketOpenSampler::new("open", &url, Default::default()).proxy(socks).run(&mut ctx).await;
+        assert_eq!(re.get_failure_message(), Some("websockets cannot go through the socks proxy socks5://127.0.0.1:1080"));
+    }
+
+    #[tokio::test]
+    async fn keep_connection() {
+        let mut ctx = VUContext::new(1, "Thread Group 1-1");
+        let url = echo_server().await;
+        let read = |timeout: u64| WebSocketReadSampler::new("read", Duration::from_millis(timeout));
+
+        let re = WebSocketWriteSampler::new("write", Frame::Text("hello".to_string())).run(&mut ctx).await;
+        assert_eq!(re.get_failure_message(), Some("no websocket connection, see WebSocketOpenSampler"));
+
+        let re = WebSocketOpenSampler::new("open", &url, Default::default()).protocol("echo").run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.get_response_code(), 101);
+        assert_eq!(re.response_result().unwrap().headers().get("sec-websocket-protocol").map(String::as_str), Some("echo"));
+        assert!(re.get_bytes() > 0 && re.get_sent_bytes() > 0);
+
+        ctx.vars_mut().set("name", "rumeter");
+        let re = WebSocketWriteSampler::new("write", Frame::Text("hello ${name}".to_string())).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.get_sent_bytes(), 6 + "hello rumeter".len() as u64);
+        let re = read(1000).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.response_result().unwrap().response_data(), "hello rumeter");
+
+        WebSocketWriteSampler::new("write", Frame::Binary(vec![0, 159])).run(&mut ctx).await;
+        let re = read(1000).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_bytes(), [0, 159]);
+
+        // the burst arrives while pinging, and is read after
+        WebSocketWriteSampler::new("write", Frame::Text("burst".to_string())).run(&mut ctx).await;
+        let re = WebSocketPingSampler::new("ping", Duration::from_secs(1)).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        let re = read(1000).filter(MessageFilter::json_path("$.n", Some(json!(1))).unwrap()).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_data(), "{\"n\":1}");
+        assert_eq!((re.get_bytes(), re.get_latency()), (2 + 7, 0));
+        let re = read(1000).filter(MessageFilter::Text(TextPattern::contains("\"n\":\\d").unwrap())).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_data(), "{\"n\":0}");
+        assert_eq!(re.get_bytes(), 2 + 7);
+        let re = read(1000).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_data(), "{\"n\":2}");
+
+        // the messages skipped by a filter are kept for the next reads
+        WebSocketWriteSampler::new("write", Frame::Text("burst".to_string())).run(&mut ctx).await;
+        let re = read(1000).filter(MessageFilter::json_path("$.n", Some(json!(2))).unwrap()).run(&mut ctx).await;
+        assert_eq!(re.response_result().unwrap().response_data(), "{\"n\":2}");
+        assert_eq!(re.get_bytes(), 2 + 7);
+        for n in [0, 1] {
+            let re = read(1000).run(&mut ctx).await;
+            assert_eq!(re.response_result().unwrap().response_data(), format!("{{\"n\":{}}}", n));
+        }
+
+        let re = read(50).run(&mut ctx).await;
+        assert_eq!((re.is_success(), re.get_response_code()), (false, TIMEOUT_CODE));
+
+        let re = WebSocketCloseSampler::new("close", Duration::from_secs(1)).run(&mut ctx).await;
+        assert!(re.is_success(), "{:?}", re.get_failure_message());
+        assert_eq!(re.get_response_code(), 1000);
+        assert!(!read(50).run(&mut ctx).await.is_success());
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashMap, io, pin::Pin, sync::{Arc, atomic::{AtomicU64, Ordering}}, task::{Context, Poll}, time::{Duration, Instant}};
+
+use reqwest::{Url, header::{HeaderMap, HeaderValue, COOKIE, SEC_WEBSOCKET_PROTOCOL}};
+use rustls::pki_types::ServerName;
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf}, net::TcpStream};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::{WebSocketStream, tungstenite::{self, client::IntoClientRequest}};
+
+use crate::{context::VUContext, config::cookie::CookieJar};
+
+use super::{client::TimeoutKind, exchange::ConnectError, proxy::ProxyConfig, tls::{TlsContext, TlsSession}};
+
+/// Any stream a websocket runs over, plain or TLS.
+pub(crate) trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+pub(crate) type WsStream = WebSocketStream<Counted<Box<dyn Io>>>;
+
+/// Bytes a connection sent and received so far, upgrade and framing included.
+#[derive(Debug, Default)]
+pub(crate) struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl Traffic {
+    pub(crate) fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// A stream adding what goes through it to its [`Traffic`].
+pub(crate) struct Counted<S> {
+    inner: S,
+    traffic: Arc<Traffic>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.traffic.received.fetch_add((buf.filled().len() - before) as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.traffic.sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// An open websocket and how it was opened.
+pub(crate) struct WsConnection {
+    pub(crate) stream: WsStream,
+    pub(crate) traffic: Arc<Traffic>,
+    /// Time of the TCP connection and the TLS handshake.
+    pub(crate) connect: Duration,
+    pub(crate) tls: Option<TlsSession>,
+    pub(crate) response_code: u16,
+    pub(crate) response_message: String,
+    pub(crate) response_headers: HashMap<String, String>,
+}
+
+/// The websocket url of `url`, `http` and `https` becoming `ws` and `wss`.
+pub(crate) fn ws_url(url: &str) -> String {
+    match url.split_once("://") {
+        Some(("http", rest)) => format!("ws://{}", rest),
+        Some(("https", rest)) => format!("wss://{}", rest),
+        _ => url.to_string(),
+    }
+}
+
+/// Open a websocket to a `ws://` or `wss://` url with the TLS of the client
+/// and the cookies of the virtual user, asking for the sub-`protocols` if any.
+/// A http or https `proxy` is asked for a tunnel, socks proxies are not supported.
+pub(crate) async fn connect(
+    ctx: &mut VUContext,
+    url: &str,
+    mut headers: HeaderMap,
+    protocols: &[&str],
+    proxy: Option<&ProxyConfig>,
+    connect_timeout: Option<Duration>,
+) -> Result<WsConnection, ConnectError> {
+    let parsed = Url::parse(url).map_err(|e| ConnectError::Failed(format!("invalid url {}: {}", url, e)))?;
+    let secure = match parsed.scheme() {
+        "ws" => false,
+        "wss" => true,
+        scheme => return Err(ConnectError::Failed(format!("not a websocket url scheme: {}", scheme))),
+    };
+    let host = parsed.host_str().unwrap_or("").trim_start_matches('[').trim_end_matches(']').to_string();
+    let port = parsed.port_or_known_default().unwrap_or(if secure { 443 } else { 80 });
+    let tls = ctx.client().tls();
+    let proxy = proxy.filter(|p| p.applies_to(&parsed));
+
+    let start = Instant::now();
+    let opening = async {
+        let io: Box<dyn Io> = match proxy {
+            Some(proxy) => {
+                let authority = format!("{}:{}", parsed.host_str().unwrap_or(""), port);
+                tunnel(proxy, &tls, &authority).await.map_err(ConnectError::Failed)?
+            },
+            None => Box::new(TcpStream::connect((host.as_str(), port)).await.map_err(|e| ConnectError::Failed(e.to_string()))?),
+        };
+        if !secure {
+            return Ok::<_, ConnectError>((io, None));
+        }
+        tls_connect(&tls, &host, io).await.map_err(ConnectError::Failed)
+    };
+    let (io, tls) = match connect_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, opening).await.map_err(|_| ConnectError::Timeout(TimeoutKind::Connect))??,
+        None => opening.await?,
+    };
+    let connect = start.elapsed();
+
+    if let Some(cookies) = ctx.get::<CookieJar>().and_then(|jar| jar.request_header(&parsed)) {
+        headers.insert(COOKIE, cookies);
+    }
+    if !protocols.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&protocols.join(", ")) {
+            headers.insert(SEC_WEBSOCKET_PROTOCOL, value);
+        }
+    }
+    let mut req = url.into_client_request().map_err(|e| ConnectError::Failed(e.to_string()))?;
+    req.headers_mut().extend(headers);
+
+    let traffic = Arc::new(Traffic::default());
+    let counted: Counted<Box<dyn Io>> = Counted { inner: io, traffic: traffic.clone() };
+    let (stream, resp) = match tokio_tungstenite::client_async(req, counted).await {
+        Ok(opened) => opened,
+        Err(tungstenite::Error::Http(resp)) => {
+            let status = resp.status();
+            return Err(ConnectError::Refused(status.as_u16(), status.canonical_reason().unwrap_or("Unknown").to_string()));
+        },
+        Err(e) => return Err(ConnectError::Failed(e.to_string())),
+    };
+    let response_headers = resp.headers().iter()
+        .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).to_string()))
+        .collect();
+    Ok(WsConnection {
+        stream,
+        traffic,
+        connect,
+        tls,
+        response_code: resp.status().as_u16(),
+        response_message: resp.status().canonical_reason().unwrap_or("Switching Protocols").to_string(),
+        response_headers,
+    })
+}
+
+async fn tls_connect(tls: &TlsContext, host: &str, io: Box<dyn Io>) -> Result<(Box<dyn Io>, Option<TlsSession>), String> {
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    let connector = TlsConnector::from(Arc::new(tls.client_config(&[b"http/1.1"])));
+    let stream = connector.connect(name, io).await.map_err(|e| e.to_string())?;
+    let session = TlsSession::of(stream.get_ref().1);
+    Ok((Box::new(stream), session))
+}
+
+/// A connection to `authority` through a http CONNECT tunnel of `proxy`.
+async fn tunnel(proxy: &ProxyConfig, tls: &TlsContext, authority: &str) -> Result<Box<dyn Io>, String> {
+    let url = Url::parse(proxy.get_url()).map_err(|e| e.to_string())?;
+    if url.scheme().starts_with("socks") {
+        return Err(format!("websockets cannot go through the socks proxy {}", proxy.get_url()));
+    }
+    let host = url.host_str().unwrap_or("").trim_start_matches('[').trim_end_matches(']');
+    let tcp = TcpStream::connect((host, url.port_or_known_default().unwrap_or(80))).await
+        .map_err(|e| format!("cannot connect to the proxy {}: {}", proxy.get_url(), e))?;
+    let mut io: Box<dyn Io> = Box::new(tcp);
+    if url.scheme() == "https" {
+        io = tls_connect(tls, host, io).await?.0;
+    }
+
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(value) = proxy.authorization() {
+        req.push_str(&format!("Proxy-Authorization: {}\r\n", value));
+    }
+    req.push_str("\r\n");
+    io.write_all(req.as_bytes()).await.map_err(|e| e.to_string())?;
+    // a byte at a time, what follows the head belongs to the tunnel
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 8192 {
+            return Err("proxy answer too long".to_string());
+        }
+        head.push(io.read_u8().await.map_err(|e| format!("proxy closed the connection: {}", e))?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status = head.lines().next().and_then(|line| line.split_once(' ')).map_or("", |(_, status)| status);
+    if !status.starts_with('2') {
+        return Err(format!("proxy refused the tunnel: {}", status));
+    }
+    Ok(io)
+}
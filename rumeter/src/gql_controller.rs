@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+use rumeter_component::{samplers::gql::DynamicGraphQLSampler, Controller, record::RecordData, context::VUContext, config::header::HeaderManager};
+
+
+#[derive(Clone)]
+pub struct GraphQLController{
+    sampler: DynamicGraphQLSampler,
+    headers: HeaderManager,
+}
+
+impl GraphQLController {
+    /// `headers` are pushed over the ones of the thread group while the sampler runs.
+    pub fn new(sampler: DynamicGraphQLSampler, headers: HeaderManager) -> Self {
+        Self { sampler, headers }
+    }
+}
+
+#[async_trait]
+impl Controller for GraphQLController {
+    async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
+        ctx.push_headers(self.headers.clone());
+        let re = ctx.sample(&self.sampler).await;
+        ctx.pop_headers();
+        vec![re]
+    }
+}
@@ -1,32 +1,35 @@
 use async_trait::async_trait;
-use rumeter_component::{samplers::http::{Method, HeaderMap, HttpSampler}, Controller, record::RecordData, Sampler};
+use rumeter_component::{samplers::http::{Method, HeaderMap, HttpSampler}, Controller, record::RecordData, context::VUContext, config::header::HeaderManager};
 
 
 #[derive(Clone)]
 pub struct HttpController{
     method: Method,
     url: String,
-    headers: HeaderMap,
+    headers: HeaderManager,
     body: Option<String>,
 }
 
 impl HttpController {
-    pub fn new(method: Method, url: &str, headers: HeaderMap, body: Option<String>) -> Self {
+    /// `headers` are pushed over the ones of the thread group while the sampler runs.
+    pub fn new(method: Method, url: &str, headers: HeaderManager, body: Option<String>) -> Self {
         Self { method, url: url.to_string(), headers, body }
     }
 }
 
 #[async_trait]
 impl Controller for HttpController {
-    async fn run(&self) -> Vec<RecordData> {
+    async fn run(&self, ctx: &mut VUContext) -> Vec<RecordData> {
         let samp = HttpSampler::new(
             "Http sampler",
             &self.url, 
             self.method.clone(), 
-            self.headers.clone(),
+            HeaderMap::new(),
             self.body.clone(),
         );
-        let re = samp.run().await;
+        ctx.push_headers(self.headers.clone());
+        let re = ctx.sample(&samp).await;
+        ctx.pop_headers();
         vec![re]
     }
 }
\ No newline at end of file
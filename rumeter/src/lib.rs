@@ -1 +1,2 @@
-pub mod http_controller;
\ No newline at end of file
+pub mod http_controller;
+pub mod gql_controller;
\ No newline at end of file